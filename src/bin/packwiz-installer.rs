@@ -1,12 +1,34 @@
-use clap::Parser;
+use packwiz_installer::cli::OutputFormat;
 use tracing_subscriber::{EnvFilter, fmt};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
+    let cli = match packwiz_installer::config::parse_args() {
+        Ok(cli) => cli,
+        Err(e) => match e.downcast::<clap::Error>() {
+            Ok(e) => e.exit(),
+            Err(e) => {
+                eprintln!("Error: {e:?}");
+                std::process::exit(packwiz_installer::exit_code(&e));
+            }
+        },
+    };
+
     // Initialize logging with RUST_LOG override, default to info
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    fmt().with_env_filter(env_filter).init();
+    if cli.output == OutputFormat::Text {
+        fmt().with_env_filter(env_filter).init();
+    } else {
+        // keep stdout for the JSON output
+        fmt()
+            .with_env_filter(env_filter)
+            .with_writer(std::io::stderr)
+            .with_ansi(false)
+            .init();
+    }
 
-    let cli = packwiz_installer::cli::Cli::parse();
-    packwiz_installer::run(cli).await
+    if let Err(e) = packwiz_installer::run(cli).await {
+        eprintln!("Error: {e:?}");
+        std::process::exit(packwiz_installer::exit_code(&e));
+    }
 }
@@ -1,36 +1,235 @@
 use crate::destination::side::Side;
-use clap::{ArgAction, Parser, ValueEnum};
-use std::path::PathBuf;
+use crate::request::secret::Secret;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OptionalMode {
     Default,
     All,
     None,
 }
 
+/// How results are printed on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human-readable output and logs
+    Text,
+    /// A single result document when the run ends
+    Json,
+    /// One JSON event per line while running, ending with the result document
+    Ndjson,
+}
+
+/// Modpack formats `export` can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Modrinth .mrpack
+    Modrinth,
+    /// CurseForge modpack zip with a manifest.json
+    Curseforge,
+}
+
+impl ExportFormat {
+    /// The format an export file name asks for: `.mrpack` or `.zip`
+    pub fn of(path: &Path) -> anyhow::Result<Self> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".mrpack") {
+            Ok(ExportFormat::Modrinth)
+        } else if name.ends_with(".zip") {
+            Ok(ExportFormat::Curseforge)
+        } else {
+            anyhow::bail!(
+                "can't tell the export format of {}; pass --format or use a .mrpack or .zip file name",
+                path.display()
+            )
+        }
+    }
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(
     name = "packwiz-installer",
     version,
-    about = "Rust port of packwiz-installer (CLI only)"
+    about = "Rust port of packwiz-installer (CLI only)",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 pub struct Cli {
-    /// Side to install mods from (client/server/both)
-    #[arg(short = 's', long = "side", value_enum, default_value_t = Side::Client)]
-    pub side: Side,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Output format on stdout; with json/ndjson logs go to stderr
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Without a subcommand the flags of `install` are accepted directly, like the Java bootstrap
+    #[command(flatten)]
+    pub install: InstallArgs,
+
+    /// Where each setting came from, filled in by [`crate::config::parse_args`]
+    #[arg(skip)]
+    pub config: crate::config::ConfigSources,
+    /// Per-host credentials from the config files
+    #[arg(skip)]
+    pub auth: Vec<crate::request::auth::HostAuth>,
+}
+
+impl Cli {
+    /// A copy safe to log: passwords in URLs are hidden (secrets already are)
+    pub fn redacted(&self) -> Self {
+        use crate::request::auth::redact_url;
+        let mut cli = self.clone();
+        let install = |a: &mut InstallArgs| {
+            a.pack_uri = a.pack_uri.as_deref().map(redact_url);
+            a.network.proxy = a.network.proxy.as_deref().map(redact_url);
+        };
+        install(&mut cli.install);
+        match &mut cli.command {
+            Some(
+                Command::Install(a)
+                | Command::Update(a)
+                | Command::Plan(a)
+                | Command::Config(ConfigCommand::Show(a)),
+            ) => install(a),
+            Some(Command::Bundle(a)) => install(&mut a.install),
+            Some(Command::Export(a)) => install(&mut a.install),
+            Some(Command::Info(a)) => {
+                a.pack_uri = redact_url(&a.pack_uri);
+                a.network.proxy = a.network.proxy.as_deref().map(redact_url);
+            }
+            Some(Command::Verify(_) | Command::Clean(_)) | None => {}
+        }
+        cli
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Install or update the pack (the default when no subcommand is given)
+    Install(InstallArgs),
+    /// Update an existing installation; fails if nothing is installed yet
+    Update(InstallArgs),
+    /// Check installed files against the manifest without using the network
+    Verify(FolderArgs),
+    /// Show what an install would download, keep and remove without changing anything
+    Plan(InstallArgs),
+    /// Remove every file tracked by the manifest, and the manifest itself
+    Clean(FolderArgs),
+    /// Print the pack's name, version, [versions] table and file counts
+    Info(InfoArgs),
+    /// Download a pack and everything it references into one archive, for installing without internet access
+    Bundle(BundleArgs),
+    /// Write the pack as a Modrinth .mrpack or a CurseForge modpack zip
+    Export(ExportArgs),
+    /// Inspect the installer's configuration files
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print the effective settings after merging config files and flags
+    Show(InstallArgs),
+}
 
+#[derive(Args, Debug, Clone)]
+pub struct FolderArgs {
     /// Folder to install the pack to (defaults to the JAR directory in Java impl; here default is current dir)
     #[arg(long = "pack-folder")]
     pub pack_folder: Option<PathBuf>,
 
-    /// The MultiMC pack folder (defaults to the parent of the pack directory in Java impl)
-    #[arg(long = "multimc-folder")]
-    pub multimc_folder: Option<PathBuf>,
-
     /// JSON file to store pack metadata, relative to the pack folder (defaults to packwiz.json)
     #[arg(long = "meta-file", default_value = "packwiz.json")]
     pub meta_file: String,
+}
+
+impl FolderArgs {
+    pub fn pack_folder(&self) -> PathBuf {
+        self.pack_folder
+            .clone()
+            .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct BundleArgs {
+    #[command(flatten)]
+    pub install: InstallArgs,
+
+    /// Archive to write (.zip, .tar.zst or .tzst)
+    #[arg(long = "archive", value_name = "PATH")]
+    pub archive: PathBuf,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub install: InstallArgs,
+
+    /// File to write (.mrpack or .zip)
+    #[arg(long = "archive", value_name = "PATH")]
+    pub archive: PathBuf,
+
+    /// Modpack format (defaults to modrinth for .mrpack and curseforge for .zip)
+    #[arg(long = "format", value_enum)]
+    pub format: Option<ExportFormat>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct InfoArgs {
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    /// pack.toml URI/path to describe
+    pub pack_uri: String,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct NetworkArgs {
+    /// HTTP(S) or SOCKS5 proxy for all requests (defaults to the HTTPS_PROXY/HTTP_PROXY environment variables)
+    #[arg(long = "proxy")]
+    pub proxy: Option<String>,
+
+    /// Proxy credentials as `user:password`
+    #[arg(long = "proxy-auth", value_name = "USER:PASSWORD")]
+    pub proxy_auth: Option<Secret>,
+
+    /// Comma-separated hosts that bypass the proxy (defaults to the NO_PROXY environment variable)
+    #[arg(long = "no-proxy", value_name = "HOSTS")]
+    pub no_proxy: Option<String>,
+
+    /// PEM file with extra trusted root certificates (repeatable)
+    #[arg(long = "ca-cert", value_name = "PEM", action = ArgAction::Append)]
+    pub ca_certs: Vec<PathBuf>,
+
+    /// PEM file with a client certificate for mutual TLS; may also hold the private key
+    #[arg(long = "client-cert", value_name = "PEM")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for --client-cert
+    #[arg(long = "client-key", value_name = "PEM", requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct InstallArgs {
+    /// Side to install mods from (client/server/both)
+    #[arg(short = 's', long = "side", value_enum, default_value_t = Side::Client)]
+    pub side: Side,
+
+    #[command(flatten)]
+    pub folder: FolderArgs,
+
+    /// The MultiMC pack folder (defaults to the parent of the pack directory in Java impl)
+    #[arg(long = "multimc-folder")]
+    pub multimc_folder: Option<PathBuf>,
 
     /// Seconds to wait before automatically launching when asking about optional mods (defaults to 10)
     #[arg(short = 't', long = "timeout", default_value_t = 10u64)]
@@ -40,10 +239,101 @@ pub struct Cli {
     #[arg(long = "optional-mode", value_enum, default_value_t = OptionalMode::Default)]
     pub optional_mode: OptionalMode,
 
+    /// Enable an optional mod by name or metafile path (globs allowed; repeatable)
+    #[arg(long = "enable", value_name = "NAME|PATH", action = ArgAction::Append)]
+    pub enable: Vec<String>,
+
+    /// Disable an optional mod by name or metafile path (globs allowed; repeatable)
+    #[arg(long = "disable", value_name = "NAME|PATH", action = ArgAction::Append)]
+    pub disable: Vec<String>,
+
+    /// TOML or JSON file with `enable` and `disable` lists of optional mods
+    #[arg(long = "options-file")]
+    pub options_file: Option<PathBuf>,
+
     /// Title of the installer window (ignored in Rust CLI, accepted for compatibility)
     #[arg(long = "title", action = ArgAction::Set)]
     pub title: Option<String>,
 
-    /// pack.toml URI/path to install from
-    pub pack_uri: String,
+    /// Modrinth API base URL used to recover stale download URLs
+    #[arg(long = "modrinth-api-url", default_value = crate::MODRINTH_API_BASE)]
+    pub modrinth_api_url: String,
+
+    /// CurseForge API base URL (e.g. a local mirror or mock service)
+    #[arg(long = "curseforge-api-url", default_value = crate::CURSEFORGE_API_BASE)]
+    pub curseforge_api_url: String,
+
+    /// CurseForge API key (overrides CF_API_KEY)
+    #[arg(long = "curseforge-api-key")]
+    pub curseforge_api_key: Option<Secret>,
+
+    /// File containing the CurseForge API key (overrides CF_API_KEY_FILE)
+    #[arg(long = "curseforge-api-key-file")]
+    pub curseforge_api_key_file: Option<PathBuf>,
+
+    /// User-Agent sent to the CurseForge API
+    #[arg(long = "user-agent", default_value = crate::DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+
+    /// Folder to watch for manually downloaded files (defaults to the user's downloads folder)
+    #[arg(long = "downloads-dir")]
+    pub downloads_dir: Option<PathBuf>,
+
+    /// Seconds to wait for manual downloads to appear when running in a terminal
+    #[arg(long = "manual-download-wait", default_value_t = 300u64)]
+    pub manual_download_wait: u64,
+
+    /// Install the server launcher for the pack's mod loader (Fabric, Forge or NeoForge) into the pack folder
+    #[arg(long = "install-loader")]
+    pub install_loader: bool,
+
+    /// Fabric meta server used to fetch the server launcher
+    #[arg(long = "fabric-meta-url", default_value = crate::task::loader::FABRIC_META_URL)]
+    pub fabric_meta_url: String,
+
+    /// Maven repository hosting Forge installers
+    #[arg(long = "forge-maven-url", default_value = crate::task::loader::FORGE_MAVEN_URL)]
+    pub forge_maven_url: String,
+
+    /// Maven repository hosting NeoForge installers
+    #[arg(long = "neoforge-maven-url", default_value = crate::task::loader::NEOFORGE_MAVEN_URL)]
+    pub neoforge_maven_url: String,
+
+    /// Java executable used to run Forge/NeoForge installers
+    #[arg(long = "java", default_value = "java")]
+    pub java: PathBuf,
+
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    /// How many files to fetch at the same time
+    #[arg(long = "concurrency", default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    pub concurrency: u16,
+
+    /// Folder for the installer's cache (defaults to the user's cache folder)
+    #[arg(long = "cache-dir")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Don't check for updates; only verify the installed files against the manifest,
+    /// without downloading or restoring anything
+    #[arg(long = "offline")]
+    pub offline: bool,
+
+    /// pack.toml URI/path to install from (may also be set as `pack-uri` in packwiz-installer.toml)
+    pub pack_uri: Option<String>,
+}
+
+impl InstallArgs {
+    pub fn pack_uri(&self) -> &str {
+        // checked by config::parse_args for the commands that need it
+        self.pack_uri.as_deref().unwrap_or_default()
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone().unwrap_or_else(|| {
+            dirs::cache_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join("packwiz-installer")
+        })
+    }
 }
@@ -0,0 +1,637 @@
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::cli::{
+    Cli, Command, ConfigCommand, FolderArgs, InstallArgs, NetworkArgs, OptionalMode, OutputFormat,
+};
+use crate::destination::side::Side;
+use crate::request::auth::HostAuth;
+use crate::request::secret::Secret;
+
+/// Config file read from the pack folder
+pub const PACK_CONFIG_FILE: &str = "packwiz-installer.toml";
+/// The only settings read from [`PACK_CONFIG_FILE`]. The pack could ship that
+/// file itself, so it must not be able to change hosts, credentials or programs.
+const PACK_CONFIG_KEYS: &[&str] = &["side", "optional-mode", "concurrency"];
+/// Overrides the location of the user-level config file
+pub const CONFIG_ENV: &str = "PACKWIZ_INSTALLER_CONFIG";
+
+/// Defaults for the command-line flags, keyed by flag name without the dashes.
+/// Relative paths are resolved against the folder holding the file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct InstallerConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub side: Option<Side>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack_folder: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multimc_folder: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optional_mode: Option<OptionalMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modrinth_api_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curseforge_api_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curseforge_api_key: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curseforge_api_key_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloads_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual_download_wait: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_loader: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fabric_meta_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forge_maven_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub neoforge_maven_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub java: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_auth: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offline: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFormat>,
+    /// Credentials per host, as `[[auth]]` tables
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth: Vec<HostAuth>,
+}
+
+/// The config files that were considered and the origin of every setting they supplied
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    /// Each config file looked at, and whether it existed
+    pub files: Vec<(PathBuf, bool)>,
+    /// Setting name to the config file it was taken from
+    pub settings: BTreeMap<&'static str, PathBuf>,
+    /// Settings given as flags
+    pub command_line: BTreeSet<&'static str>,
+    /// Problems found while reading the files, logged once logging is set up
+    pub warnings: Vec<String>,
+}
+
+/// The user-level config file: `$PACKWIZ_INSTALLER_CONFIG`, or
+/// `packwiz-installer/config.toml` in the user's config folder
+pub fn user_config_path() -> Option<PathBuf> {
+    config_path_from(std::env::var_os(CONFIG_ENV))
+}
+
+fn config_path_from(env: Option<std::ffi::OsString>) -> Option<PathBuf> {
+    match env {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => dirs::config_dir().map(|d| d.join("packwiz-installer").join("config.toml")),
+    }
+}
+
+impl InstallerConfig {
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let mut config: InstallerConfig =
+            toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(Some(config))
+    }
+
+    /// Keeps only the settings allowed in the pack folder, returning the names of the others
+    fn retain_pack_keys(&mut self) -> Vec<String> {
+        let ignored = toml::Table::try_from(&*self)
+            .map(|t| {
+                t.keys()
+                    .filter(|k| !PACK_CONFIG_KEYS.contains(&k.as_str()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        *self = InstallerConfig {
+            side: self.side,
+            optional_mode: self.optional_mode,
+            concurrency: self.concurrency,
+            ..Default::default()
+        };
+        ignored
+    }
+
+    fn resolve_paths(&mut self, dir: &Path) {
+        for path in [
+            &mut self.pack_folder,
+            &mut self.multimc_folder,
+            &mut self.options_file,
+            &mut self.curseforge_api_key_file,
+            &mut self.downloads_dir,
+            &mut self.cache_dir,
+            &mut self.client_cert,
+            &mut self.client_key,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.ca_cert.iter_mut().flatten())
+        {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        }
+    }
+
+    /// Fills in the install flags that weren't given on the command line
+    fn apply(
+        &self,
+        args: &mut InstallArgs,
+        m: &ArgMatches,
+        origin: &Path,
+        sources: &mut ConfigSources,
+    ) {
+        self.apply_install(args, m, origin, sources);
+        self.apply_folder(&mut args.folder, m, origin, sources);
+        self.apply_network(&mut args.network, m, origin, sources);
+    }
+
+    /// The effective settings of an install, in config file form
+    pub fn effective(args: &InstallArgs, output: OutputFormat, auth: &[HostAuth]) -> Self {
+        InstallerConfig {
+            pack_uri: args.pack_uri.clone(),
+            side: Some(args.side),
+            pack_folder: Some(args.folder.pack_folder()),
+            meta_file: Some(args.folder.meta_file.clone()),
+            multimc_folder: args.multimc_folder.clone(),
+            timeout: Some(args.timeout_secs),
+            optional_mode: Some(args.optional_mode),
+            enable: Some(args.enable.clone()),
+            disable: Some(args.disable.clone()),
+            options_file: args.options_file.clone(),
+            modrinth_api_url: Some(args.modrinth_api_url.clone()),
+            curseforge_api_url: Some(args.curseforge_api_url.clone()),
+            curseforge_api_key: args.curseforge_api_key.clone(),
+            curseforge_api_key_file: args.curseforge_api_key_file.clone(),
+            user_agent: Some(args.user_agent.clone()),
+            downloads_dir: args.downloads_dir.clone(),
+            manual_download_wait: Some(args.manual_download_wait),
+            install_loader: Some(args.install_loader),
+            fabric_meta_url: Some(args.fabric_meta_url.clone()),
+            forge_maven_url: Some(args.forge_maven_url.clone()),
+            neoforge_maven_url: Some(args.neoforge_maven_url.clone()),
+            java: Some(args.java.clone()),
+            proxy: args.network.proxy.clone(),
+            proxy_auth: args.network.proxy_auth.clone(),
+            no_proxy: args.network.no_proxy.clone(),
+            ca_cert: Some(args.network.ca_certs.clone()),
+            client_cert: args.network.client_cert.clone(),
+            client_key: args.network.client_key.clone(),
+            concurrency: Some(args.concurrency),
+            cache_dir: Some(args.cache_dir()),
+            offline: Some(args.offline),
+            output: Some(output),
+            auth: auth.to_vec(),
+        }
+    }
+}
+
+/// Whether an argument was given explicitly rather than left at its default
+fn given(m: &ArgMatches, id: &str) -> bool {
+    m.try_get_raw(id).is_ok()
+        && matches!(
+            m.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+}
+
+/// Declares every config key once: `key => config field -> argument`, with
+/// `Some` for arguments that are optional. Generates an `apply_*` method per
+/// argument struct, filling in the arguments that weren't given, and [`KEYS`].
+macro_rules! config_keys {
+    (@value $v:expr, Some) => {
+        Some($v)
+    };
+    (@value $v:expr) => {
+        $v
+    };
+    (
+        $(fn $apply:ident($args:ty) {
+            $($key:literal => $field:ident -> $arg:ident $($some:ident)?,)*
+        })*
+        $($other_key:literal -> $other_arg:ident,)*
+    ) => {
+        impl InstallerConfig {
+            $(fn $apply(
+                &self,
+                args: &mut $args,
+                m: &ArgMatches,
+                origin: &Path,
+                sources: &mut ConfigSources,
+            ) {
+                $(if let Some(v) = &self.$field
+                    && !given(m, stringify!($arg))
+                {
+                    sources.settings.insert($key, origin.to_path_buf());
+                    args.$arg = config_keys!(@value v.clone() $(, $some)?);
+                })*
+            })*
+        }
+
+        /// Config keys and the ids of the arguments they stand for
+        const KEYS: &[(&str, &str)] = &[
+            $($(($key, stringify!($arg)),)*)*
+            $(($other_key, stringify!($other_arg)),)*
+        ];
+    };
+}
+
+config_keys! {
+    fn apply_install(InstallArgs) {
+        "pack-uri" => pack_uri -> pack_uri Some,
+        "side" => side -> side,
+        "multimc-folder" => multimc_folder -> multimc_folder Some,
+        "timeout" => timeout -> timeout_secs,
+        "optional-mode" => optional_mode -> optional_mode,
+        "enable" => enable -> enable,
+        "disable" => disable -> disable,
+        "options-file" => options_file -> options_file Some,
+        "modrinth-api-url" => modrinth_api_url -> modrinth_api_url,
+        "curseforge-api-url" => curseforge_api_url -> curseforge_api_url,
+        "curseforge-api-key" => curseforge_api_key -> curseforge_api_key Some,
+        "curseforge-api-key-file" => curseforge_api_key_file -> curseforge_api_key_file Some,
+        "user-agent" => user_agent -> user_agent,
+        "downloads-dir" => downloads_dir -> downloads_dir Some,
+        "manual-download-wait" => manual_download_wait -> manual_download_wait,
+        "install-loader" => install_loader -> install_loader,
+        "fabric-meta-url" => fabric_meta_url -> fabric_meta_url,
+        "forge-maven-url" => forge_maven_url -> forge_maven_url,
+        "neoforge-maven-url" => neoforge_maven_url -> neoforge_maven_url,
+        "java" => java -> java,
+        "concurrency" => concurrency -> concurrency,
+        "cache-dir" => cache_dir -> cache_dir Some,
+        "offline" => offline -> offline,
+    }
+    fn apply_folder(FolderArgs) {
+        "pack-folder" => pack_folder -> pack_folder Some,
+        "meta-file" => meta_file -> meta_file,
+    }
+    fn apply_network(NetworkArgs) {
+        "proxy" => proxy -> proxy Some,
+        "proxy-auth" => proxy_auth -> proxy_auth Some,
+        "no-proxy" => no_proxy -> no_proxy Some,
+        "ca-cert" => ca_cert -> ca_certs,
+        "client-cert" => client_cert -> client_cert Some,
+        "client-key" => client_key -> client_key Some,
+    }
+    // applied to the top level by `parse_matches`
+    "output" -> output,
+}
+
+/// Parses the command line and fills in everything it leaves out from the
+/// pack folder's `packwiz-installer.toml`, then the user-level config file.
+pub fn parse_args() -> Result<Cli> {
+    parse_matches(Cli::command().get_matches(), user_config_path())
+}
+
+fn parse_matches(matches: ArgMatches, user_config: Option<PathBuf>) -> Result<Cli> {
+    let mut cli = Cli::from_arg_matches(&matches)?;
+
+    // Settings of the subcommand, or of the top level for the legacy invocation
+    let (name, sub) = match matches.subcommand() {
+        Some(("config", config)) => match config.subcommand() {
+            Some((name, m)) => (name, m),
+            None => ("config", config),
+        },
+        Some((name, m)) => (name, m),
+        None => ("install", &matches),
+    };
+
+    let mut layers = Vec::new();
+    let mut sources = ConfigSources::default();
+    if let Some(path) = user_config {
+        let config = InstallerConfig::load(&path)?;
+        sources.files.push((path.clone(), config.is_some()));
+        layers.extend(config.map(|c| (path, c)));
+    }
+    // The pack-local file lives in the pack folder, so it can't move the pack folder
+    let pack_folder = match &cli.command {
+        Some(Command::Verify(a) | Command::Clean(a)) => a.pack_folder.clone(),
+        Some(Command::Install(a) | Command::Update(a) | Command::Plan(a))
+        | Some(Command::Config(ConfigCommand::Show(a))) => a.folder.pack_folder.clone(),
+        Some(Command::Bundle(a)) => a.install.folder.pack_folder.clone(),
+        Some(Command::Export(a)) => a.install.folder.pack_folder.clone(),
+        Some(Command::Info(_)) => None,
+        None => cli.install.folder.pack_folder.clone(),
+    }
+    .or_else(|| layers.iter().rev().find_map(|(_, c)| c.pack_folder.clone()))
+    .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")));
+    let pack_config = pack_folder.join(PACK_CONFIG_FILE);
+    let config = InstallerConfig::load(&pack_config)?;
+    sources.files.push((pack_config.clone(), config.is_some()));
+    if let Some(mut config) = config {
+        let ignored = config.retain_pack_keys();
+        if !ignored.is_empty() {
+            sources.warnings.push(format!(
+                "ignoring {} in {}; only {} may be set there, use the command line or the user config",
+                ignored.join(", "),
+                pack_config.display(),
+                PACK_CONFIG_KEYS.join(", ")
+            ));
+        }
+        layers.push((pack_config, config));
+    }
+
+    for (origin, config) in &mut layers {
+        if config.concurrency == Some(0) {
+            sources.warnings.push(format!(
+                "concurrency = 0 in {} is invalid; using 1",
+                origin.display()
+            ));
+            config.concurrency = Some(1);
+        }
+    }
+
+    // Later layers win; the command line wins over all of them
+    for (origin, config) in &layers {
+        if let Some(v) = config.output
+            && !given(&matches, "output")
+            && !given(sub, "output")
+        {
+            sources.settings.insert("output", origin.clone());
+            cli.output = v;
+        }
+        match &mut cli.command {
+            Some(Command::Verify(a) | Command::Clean(a)) => {
+                config.apply_folder(a, sub, origin, &mut sources)
+            }
+            Some(Command::Install(a) | Command::Update(a) | Command::Plan(a))
+            | Some(Command::Config(ConfigCommand::Show(a))) => {
+                config.apply(a, sub, origin, &mut sources)
+            }
+            Some(Command::Bundle(a)) => config.apply(&mut a.install, sub, origin, &mut sources),
+            Some(Command::Export(a)) => config.apply(&mut a.install, sub, origin, &mut sources),
+            Some(Command::Info(a)) => {
+                config.apply_network(&mut a.network, sub, origin, &mut sources)
+            }
+            None => config.apply(&mut cli.install, sub, origin, &mut sources),
+        }
+    }
+
+    let needs_pack_uri = match &cli.command {
+        Some(Command::Install(a) | Command::Update(a) | Command::Plan(a)) => a.pack_uri.is_none(),
+        Some(Command::Bundle(a)) => a.install.pack_uri.is_none(),
+        Some(Command::Export(a)) => a.install.pack_uri.is_none(),
+        None => cli.install.pack_uri.is_none(),
+        _ => false,
+    };
+    if needs_pack_uri {
+        let mut cmd = Cli::command();
+        cmd.build();
+        let cmd = match name {
+            "install" if matches.subcommand().is_none() => &mut cmd,
+            name => cmd
+                .find_subcommand_mut(name)
+                .expect("parsed subcommand exists"),
+        };
+        return Err(cmd
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                format!("the pack.toml URI is required (or set `pack-uri` in {PACK_CONFIG_FILE})"),
+            )
+            .into());
+    }
+    // Entries of the pack-local file come first, so they win over the user config's
+    for (origin, config) in layers.iter_mut().rev() {
+        if !config.auth.is_empty() {
+            sources
+                .settings
+                .entry("auth")
+                .or_insert_with(|| origin.clone());
+        }
+        for auth in std::mem::take(&mut config.auth) {
+            cli.auth.push(auth.resolve()?);
+        }
+    }
+    sources.command_line = KEYS
+        .iter()
+        .filter(|(_, id)| given(sub, id) || given(&matches, id))
+        .map(|(key, _)| *key)
+        .collect();
+    cli.config = sources;
+    Ok(cli)
+}
+
+/// `config show`: prints the effective settings and where each one came from
+pub fn show(
+    args: &InstallArgs,
+    output: OutputFormat,
+    sources: &ConfigSources,
+    auth: &[HostAuth],
+) -> Result<serde_json::Value> {
+    let effective = InstallerConfig::effective(args, output, auth);
+    let table = toml::Table::try_from(&effective)?;
+    if output == OutputFormat::Text {
+        for (path, found) in &sources.files {
+            let state = if *found { "" } else { " (not found)" };
+            println!("# {}{}", path.display(), state);
+        }
+        for (key, value) in &table {
+            let origin = match sources.settings.get(key.as_str()) {
+                Some(path) => path.display().to_string(),
+                None if sources.command_line.contains(key.as_str()) => "command line".to_string(),
+                None => "default".to_string(),
+            };
+            println!("{key} = {value}  # {origin}");
+        }
+    }
+    Ok(serde_json::json!({
+        "files": sources.files.iter().map(|(path, found)| serde_json::json!({
+            "path": path,
+            "found": found,
+        })).collect::<Vec<_>>(),
+        "settings": effective,
+        "sources": sources.settings,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::test_pack::TempDir;
+
+    /// Parses `args` after the program name, reading `user.toml` from `dir` as the user config
+    fn parse(dir: &TempDir, args: &[&str]) -> Cli {
+        let matches = Cli::command()
+            .try_get_matches_from(["packwiz-installer"].iter().chain(args))
+            .unwrap();
+        parse_matches(matches, Some(dir.join("user.toml"))).unwrap()
+    }
+
+    fn install(cli: &Cli) -> &InstallArgs {
+        match &cli.command {
+            Some(Command::Install(a)) => a,
+            other => panic!("not an install: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn flags_win_over_the_pack_file_and_the_pack_file_over_the_user_file() {
+        let dir = TempDir::new("config-precedence");
+        let pack = dir.join("pack");
+        std::fs::create_dir_all(&pack).unwrap();
+        std::fs::write(
+            dir.join("user.toml"),
+            "side = \"server\"\noptional-mode = \"none\"\nconcurrency = 2\nmeta-file = \"user.json\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            pack.join(PACK_CONFIG_FILE),
+            "optional-mode = \"all\"\nconcurrency = 3\n",
+        )
+        .unwrap();
+        let folder = pack.to_str().unwrap();
+        let cli = parse(
+            &dir,
+            &[
+                "install",
+                "pack.toml",
+                "--pack-folder",
+                folder,
+                "--concurrency",
+                "5",
+            ],
+        );
+        let args = install(&cli);
+        assert_eq!(args.side, Side::Server);
+        assert!(matches!(args.optional_mode, OptionalMode::All));
+        assert_eq!(args.concurrency, 5);
+        assert_eq!(args.folder.meta_file, "user.json");
+        let sources = &cli.config;
+        assert_eq!(sources.settings["side"], dir.join("user.toml"));
+        assert_eq!(
+            sources.settings["optional-mode"],
+            pack.join(PACK_CONFIG_FILE)
+        );
+        assert!(!sources.settings.contains_key("concurrency"));
+        assert!(sources.command_line.contains("concurrency"));
+        assert!(sources.command_line.contains("pack-folder"));
+        assert!(sources.warnings.is_empty(), "{:?}", sources.warnings);
+    }
+
+    #[test]
+    fn user_config_follows_the_environment() {
+        assert_eq!(
+            config_path_from(Some("/etc/packwiz.toml".into())),
+            Some(PathBuf::from("/etc/packwiz.toml"))
+        );
+        let default = dirs::config_dir().map(|d| d.join("packwiz-installer").join("config.toml"));
+        assert_eq!(config_path_from(Some("".into())), default);
+        assert_eq!(config_path_from(None), default);
+    }
+
+    #[test]
+    fn pack_file_only_sets_harmless_keys() {
+        let dir = TempDir::new("config-pack-keys");
+        std::fs::write(
+            dir.join(PACK_CONFIG_FILE),
+            "side = \"server\"\njava = \"/tmp/evil\"\ninstall-loader = true\nproxy = \"http://proxy\"\n\n\
+             [[auth]]\nhost = \"example.com\"\nbearer = \"token\"\n",
+        )
+        .unwrap();
+        let folder = dir.path().to_str().unwrap();
+        let cli = parse(&dir, &["install", "pack.toml", "--pack-folder", folder]);
+        let args = install(&cli);
+        assert_eq!(args.side, Side::Server);
+        assert_eq!(args.java, PathBuf::from("java"));
+        assert!(!args.install_loader);
+        assert_eq!(args.network.proxy, None);
+        assert!(cli.auth.is_empty());
+        let [warning] = &cli.config.warnings[..] else {
+            panic!("{:?}", cli.config.warnings)
+        };
+        assert!(
+            warning.contains("ignoring auth, install-loader, java, proxy in"),
+            "{warning}"
+        );
+    }
+
+    #[test]
+    fn relative_paths_are_resolved_against_the_config_file() {
+        let dir = TempDir::new("config-paths");
+        std::fs::write(
+            dir.join("user.toml"),
+            "cache-dir = \"cache\"\nca-cert = [\"certs/ca.pem\", \"/etc/ca.pem\"]\noptions-file = \"../options.toml\"\n",
+        )
+        .unwrap();
+        let folder = dir.path().to_str().unwrap();
+        let cli = parse(&dir, &["install", "pack.toml", "--pack-folder", folder]);
+        let args = install(&cli);
+        assert_eq!(args.cache_dir, Some(dir.join("cache")));
+        assert_eq!(
+            args.network.ca_certs,
+            [dir.join("certs/ca.pem"), PathBuf::from("/etc/ca.pem")]
+        );
+        assert_eq!(args.options_file, Some(dir.join("../options.toml")));
+    }
+
+    #[test]
+    fn zero_concurrency_is_a_warning() {
+        let dir = TempDir::new("config-concurrency");
+        std::fs::write(dir.join("user.toml"), "concurrency = 0\n").unwrap();
+        let folder = dir.path().to_str().unwrap();
+        let cli = parse(&dir, &["install", "pack.toml", "--pack-folder", folder]);
+        assert_eq!(install(&cli).concurrency, 1);
+        assert_eq!(
+            cli.config.warnings,
+            [format!(
+                "concurrency = 0 in {} is invalid; using 1",
+                dir.join("user.toml").display()
+            )]
+        );
+    }
+
+    #[test]
+    fn every_key_names_an_argument() {
+        // the top level takes the install flags directly
+        let cmd = Cli::command();
+        for (key, id) in KEYS {
+            assert!(
+                cmd.get_arguments().any(|a| a.get_id() == id),
+                "{key}: no argument {id}"
+            );
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use thiserror::Error;
+
+/// Downloaded or installed content didn't match the hash the pack declares
+#[derive(Error, Debug)]
+#[error("hash mismatch for {subject}: got {got}, expected {expected} ({format})")]
+pub struct HashMismatch {
+    /// What was checked, e.g. `mod Sodium` or `file config/foo.toml`
+    pub subject: String,
+    pub got: String,
+    pub expected: String,
+    pub format: String,
+}
+
+/// `verify` found missing or modified files
+#[derive(Error, Debug)]
+#[error("{0} installed files failed verification")]
+pub struct VerificationFailed(pub usize);
+
+/// The pack asks for something the installer refuses to do, such as an
+/// unsupported pack-format or a conflicting optional mod selection
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct PolicyViolation(pub String);
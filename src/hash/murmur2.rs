@@ -1,10 +1,25 @@
 // 32-bit MurmurHash2 compatible with Murmur2Lib.hash32 (seed 0)
 pub fn murmur2_hash(data: &[u8]) -> u32 {
+    murmur2_hash_seeded(data, 0)
+}
+
+// CurseForge file fingerprint: whitespace bytes (tab, LF, CR, space) are
+// stripped before hashing with seed 1, as packwiz does for "murmur2"
+pub fn curseforge_fingerprint(data: &[u8]) -> u32 {
+    let normalized: Vec<u8> = data
+        .iter()
+        .copied()
+        .filter(|b| !matches!(b, 9 | 10 | 13 | 32))
+        .collect();
+    murmur2_hash_seeded(&normalized, 1)
+}
+
+pub fn murmur2_hash_seeded(data: &[u8], seed: u32) -> u32 {
     const M_32: u32 = 0x5bd1e995;
     const R_32: u32 = 24;
 
     let len = data.len() as u32;
-    let mut h: u32 = len;
+    let mut h: u32 = seed ^ len;
 
     let mut i = 0usize;
     while i + 4 <= data.len() {
@@ -39,3 +54,56 @@ pub fn murmur2_hash(data: &[u8]) -> u32 {
     h ^= h >> 15;
     h
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected values come from a separate port of Austin Appleby's reference
+    // MurmurHash2, with CurseForge's whitespace stripping done before hashing.
+    // TODO: also check a jar hosted on CurseForge (kept in testdata) against
+    // the `fileFingerprint` the CurseForge API publishes for it.
+    #[test]
+    fn matches_reference_murmur2() {
+        let cases: [(&[u8], u32, u32); 6] = [
+            (b"", 0, 1540447798),
+            (b"a", 2456313694, 626045324),
+            (b"ab", 446775395, 1692487918),
+            (b"abc", 324500635, 1621425345),
+            (b"abcd", 646393889, 3376380438),
+            (
+                b"The quick brown fox jumps over the lazy dog",
+                556214736,
+                504383975,
+            ),
+        ];
+        for (data, seed0, seed1) in cases {
+            assert_eq!(murmur2_hash(data), seed0, "{data:?}");
+            assert_eq!(murmur2_hash_seeded(data, 1), seed1, "{data:?}");
+        }
+    }
+
+    #[test]
+    fn fingerprint_strips_whitespace() {
+        let plain = curseforge_fingerprint(b"Hello,world!");
+        assert_eq!(plain, 2173663876);
+        assert_eq!(curseforge_fingerprint(b"Hello, world!"), plain);
+        assert_eq!(curseforge_fingerprint(b"Hello,\tworld!\r\n"), plain);
+        assert_eq!(curseforge_fingerprint(b" H e l l o ,\nworld!"), plain);
+        assert_eq!(
+            curseforge_fingerprint(b"The quick brown fox jumps over the lazy dog"),
+            3751777527
+        );
+        // Only tab, LF, CR and space are stripped
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(curseforge_fingerprint(&all), 2094645347);
+        assert_ne!(
+            curseforge_fingerprint(b"a\x0bb"),
+            curseforge_fingerprint(b"ab")
+        );
+        assert_eq!(
+            curseforge_fingerprint(b" \t\r\n"),
+            curseforge_fingerprint(b"")
+        );
+    }
+}
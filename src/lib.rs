@@ -1,5 +1,7 @@
 pub mod cli;
+pub mod config;
 pub mod destination;
+pub mod errors;
 pub mod hash;
 pub mod metadata;
 pub mod request;
@@ -7,13 +9,16 @@ pub mod task;
 
 use anyhow::{Context, Result};
 use bytes::Bytes;
-use reqwest::{Client, Url};
+use request::errors::RequestError;
+use request::secret::Secret;
+use reqwest::header::{ACCEPT, CONTENT_TYPE, HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::{Method, StatusCode, Url};
 use serde::Deserialize;
 use sha1::{Digest as Sha1DigestTrait, Sha1};
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncReadExt;
 use tokio::time::{Duration, sleep};
-use tracing::info;
+use tracing::{debug, info, warn};
 
 fn sha256_hex(data: &[u8]) -> String {
     use sha2::{Digest, Sha256};
@@ -29,34 +34,56 @@ fn sha1_hex(data: &[u8]) -> String {
 }
 
 pub(crate) async fn fetch_bytes(uri: &str) -> Result<Bytes> {
-    // Support http(s) and file/local paths
+    // URLs go to the fetcher registered for their scheme; anything else is a local path
     if let Ok(url) = uri.parse::<Url>() {
-        match url.scheme() {
-            "http" | "https" => {
-                let client = request::client::build_http_client()?;
-                let res = client.get(url).send().await?.error_for_status()?;
-                let bytes = res.bytes().await?;
-                Ok(bytes)
-            }
-            "file" => {
-                let path = url
-                    .to_file_path()
-                    .map_err(|_| anyhow::anyhow!("invalid file:// path"))?;
-                let mut f = tokio::fs::File::open(&path).await?;
-                let mut buf = Vec::new();
-                f.read_to_end(&mut buf).await?;
-                Ok(Bytes::from(buf))
-            }
-            _ => anyhow::bail!("unsupported scheme: {}", url.scheme()),
+        match request::fetcher::get(url.scheme()) {
+            Some(fetcher) => fetcher.fetch(&url).await,
+            None => anyhow::bail!("unsupported scheme: {}", url.scheme()),
         }
     } else {
-        // Treat as local path
-        let p = Path::new(uri);
-        let data = tokio::fs::read(p).await?;
+        let data = tokio::fs::read(Path::new(uri)).await?;
         Ok(Bytes::from(data))
     }
 }
 
+/// [`fetch_bytes`] as a conditional request; local paths are always read
+pub(crate) async fn fetch_bytes_if_modified(
+    uri: &str,
+    validators: &request::fetcher::Validators,
+) -> Result<request::fetcher::Fetched> {
+    if let Ok(url) = uri.parse::<Url>() {
+        match request::fetcher::get(url.scheme()) {
+            Some(fetcher) => fetcher.fetch_if_modified(&url, validators).await,
+            None => anyhow::bail!("unsupported scheme: {}", url.scheme()),
+        }
+    } else {
+        let data = tokio::fs::read(Path::new(uri)).await?;
+        Ok(request::fetcher::Fetched::Modified(
+            Bytes::from(data),
+            Default::default(),
+        ))
+    }
+}
+
+/// Sends an API request through the fetcher registered for the URL's scheme
+pub(crate) async fn send_api(
+    method: reqwest::Method,
+    url: &str,
+    headers: reqwest::header::HeaderMap,
+    body: Option<Bytes>,
+) -> Result<request::fetcher::ApiResponse> {
+    let url = Url::parse(url).map_err(|_| RequestError::InvalidUrl(url.to_string()))?;
+    let fetcher = request::fetcher::get(url.scheme())
+        .with_context(|| format!("unsupported scheme: {}", url.scheme()))?;
+    let req = request::fetcher::ApiRequest {
+        method,
+        url,
+        headers,
+        body,
+    };
+    fetcher.send(&req).await
+}
+
 pub(crate) async fn fetch_bytes_retry(uri: &str, attempts: usize) -> Result<Bytes> {
     let mut last_err: Option<anyhow::Error> = None;
     let mut delay = Duration::from_millis(500);
@@ -74,26 +101,15 @@ pub(crate) async fn fetch_bytes_retry(uri: &str, attempts: usize) -> Result<Byte
 }
 
 pub(crate) fn join_uri(base: &str, rel: &str) -> Result<String> {
-    if rel.starts_with("http://") || rel.starts_with("https://") || rel.starts_with("file:") {
+    if let Ok(url) = rel.parse::<Url>()
+        && request::fetcher::get(url.scheme()).is_some()
+    {
         return Ok(rel.to_string());
     }
-    if let Ok(url) = base.parse::<Url>() {
-        match url.scheme() {
-            "http" | "https" => {
-                let joined = url
-                    .join(rel)
-                    .with_context(|| format!("failed to join url {url} + {rel}"))?;
-                return Ok(joined.to_string());
-            }
-            "file" => {
-                let basep = url
-                    .to_file_path()
-                    .map_err(|_| anyhow::anyhow!("invalid base file url"))?;
-                let joined = basep.parent().unwrap_or_else(|| Path::new(".")).join(rel);
-                return Ok(format!("file://{}", joined.display()));
-            }
-            _ => {}
-        }
+    if let Ok(url) = base.parse::<Url>()
+        && let Some(fetcher) = request::fetcher::get(url.scheme())
+    {
+        return Ok(fetcher.join(&url, rel)?.to_string());
     }
     // local path base
     let p = Path::new(base);
@@ -126,7 +142,7 @@ pub(crate) fn hash_hex(format: &str, data: &[u8]) -> Result<String> {
             Ok(format!("{digest:x}"))
         }
         "murmur2" => {
-            let h = crate::hash::murmur2::murmur2_hash(data);
+            let h = crate::hash::murmur2::curseforge_fingerprint(data);
             Ok(h.to_string())
         }
         other => anyhow::bail!("unsupported hash format: {}", other),
@@ -176,93 +192,553 @@ struct CfModsResp {
     data: Vec<CfMod>,
 }
 
-pub(crate) fn cf_api_key() -> String {
-    if let Ok(k) = std::env::var("CF_API_KEY") {
-        return k;
+pub const CURSEFORGE_API_BASE: &str = "https://api.curseforge.com";
+pub const DEFAULT_USER_AGENT: &str = "packwiz-installer-rust";
+
+/// Where and how to reach the CurseForge API
+#[derive(Debug, Clone)]
+pub struct CurseForgeApi {
+    pub base_url: String,
+    /// Key given on the command line or in a config file
+    pub api_key: Option<Secret>,
+    /// File holding the key, given on the command line or in a config file
+    pub api_key_file: Option<PathBuf>,
+    pub user_agent: String,
+}
+
+impl CurseForgeApi {
+    /// The API key to send; only looked up once the pack turns out to need CurseForge
+    pub fn key(&self) -> Result<Secret> {
+        cf_api_key(self.api_key.as_ref(), self.api_key_file.as_deref())
+    }
+}
+
+/// Picks the CurseForge API key from, in order: the CLI, a key file given on
+/// the CLI, `CF_API_KEY`, a key file named by `CF_API_KEY_FILE`, and finally
+/// the key embedded in the original installer.
+pub fn cf_api_key(cli_key: Option<&Secret>, cli_key_file: Option<&Path>) -> Result<Secret> {
+    pick_cf_api_key(cli_key, cli_key_file, |name| std::env::var_os(name))
+}
+
+fn pick_cf_api_key(
+    cli_key: Option<&Secret>,
+    cli_key_file: Option<&Path>,
+    env: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> Result<Secret> {
+    let read_key_file = |path: &Path| -> Result<Secret> {
+        let text = std::fs::read_to_string(path).with_context(|| {
+            format!("failed to read CurseForge API key file {}", path.display())
+        })?;
+        debug!("using CurseForge API key from {}", path.display());
+        Ok(Secret::new(text.trim()))
+    };
+    if let Some(k) = cli_key {
+        debug!("using CurseForge API key from the command line");
+        return Ok(k.clone());
+    }
+    if let Some(path) = cli_key_file {
+        return read_key_file(path);
     }
+    if let Some(k) = env("CF_API_KEY").and_then(|k| k.into_string().ok()) {
+        debug!("using CurseForge API key from CF_API_KEY");
+        return Ok(Secret::new(k));
+    }
+    if let Some(path) = env("CF_API_KEY_FILE") {
+        return read_key_file(Path::new(&path));
+    }
+    info!("no CurseForge API key configured; using the built-in key");
     let b64 = "JDJhJDEwJHNBWVhqblU1N0EzSmpzcmJYM3JVdk92UWk2NHBLS3BnQ2VpbGc1TUM1UGNKL0RYTmlGWWxh";
     use base64::Engine as _;
     let bytes = base64::engine::general_purpose::STANDARD
         .decode(b64)
         .unwrap_or_default();
-    String::from_utf8(bytes).unwrap_or_default()
+    Ok(Secret::new(String::from_utf8(bytes).unwrap_or_default()))
 }
 
-pub(crate) async fn cf_get_download_url(
-    client: &Client,
-    project_id: i64,
-    file_id: i64,
-) -> Result<std::result::Result<String, String>> {
-    let key = cf_api_key();
-    let files_req = serde_json::json!({ "fileIds": [file_id] });
-    let resp = client
-        .post("https://api.curseforge.com/v1/mods/files")
-        .header("Accept", "application/json")
-        .header("User-Agent", "packwiz-installer-rust")
-        .header("X-API-Key", key.clone())
-        .json(&files_req)
-        .send()
-        .await?;
-    let status = resp.status();
-    let body = resp.text().await?;
-    if !status.is_success() {
-        anyhow::bail!("curseforge files api error {}: {}", status, body);
+/// Longest Retry-After we are willing to sleep through before giving up
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
     }
-    let data: CfFilesResp = serde_json::from_str(&body)?;
-    if let Some(cf_file) = data.data.first()
-        && let Some(url) = cf_file.downloadUrl.clone()
-    {
-        return Ok(Ok(url));
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(
+        at.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+/// POSTs a JSON body to the CurseForge API, waiting out 429/503 responses that
+/// carry a usable Retry-After and mapping failures onto [`RequestError`].
+async fn cf_post<T: serde::de::DeserializeOwned>(
+    api: &CurseForgeApi,
+    api_key: &Secret,
+    path: &str,
+    body: &serde_json::Value,
+) -> Result<T> {
+    const SERVICE: &str = "curseforge";
+    let url = format!("{}{path}", api.base_url.trim_end_matches('/'));
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(USER_AGENT, HeaderValue::from_str(&api.user_agent)?);
+    let mut key = HeaderValue::from_str(api_key.expose())?;
+    key.set_sensitive(true);
+    headers.insert("X-API-Key", key);
+    let body = Bytes::from(serde_json::to_vec(body)?);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let resp = send_api(Method::POST, &url, headers.clone(), Some(body.clone())).await?;
+        let status = resp.status;
+        let wait = retry_after(&resp.headers);
+        let text = String::from_utf8_lossy(&resp.body).into_owned();
+        if status.is_success() {
+            return Ok(serde_json::from_str(&text)?);
+        }
+        let retryable =
+            status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE;
+        if retryable
+            && attempt < 3
+            && let Some(wait) = wait.filter(|w| *w <= MAX_RETRY_AFTER)
+        {
+            warn!(
+                "curseforge api returned {}; retrying in {}s",
+                status,
+                wait.as_secs()
+            );
+            sleep(wait).await;
+            continue;
+        }
+        let err = match status {
+            StatusCode::FORBIDDEN => RequestError::Forbidden {
+                service: SERVICE,
+                body: text,
+            },
+            StatusCode::TOO_MANY_REQUESTS => RequestError::RateLimited {
+                service: SERVICE,
+                retry_after: wait,
+            },
+            s if s.is_server_error() => RequestError::Server {
+                service: SERVICE,
+                status: s.as_u16(),
+                body: text,
+            },
+            s => RequestError::Status {
+                service: SERVICE,
+                status: s.as_u16(),
+                body: text,
+            },
+        };
+        return Err(err.into());
     }
-    // fallback: manual link via /v1/mods
-    let mods_req = serde_json::json!({ "modIds": [project_id] });
-    let resp2 = client
-        .post("https://api.curseforge.com/v1/mods")
-        .header("Accept", "application/json")
-        .header("User-Agent", "packwiz-installer-rust")
-        .header("X-API-Key", key)
-        .json(&mods_req)
-        .send()
-        .await?;
-    let status2 = resp2.status();
-    let body2 = resp2.text().await?;
-    if !status2.is_success() {
-        anyhow::bail!("curseforge mods api error {}: {}", status2, body2);
+}
+
+/// Resolves download URLs for many CurseForge files at once, keyed by file ID.
+/// Files without an API download URL map to `Err(manual_url)`.
+pub(crate) async fn cf_get_download_urls(
+    api: &CurseForgeApi,
+    ids: &[(i64, i64)],
+) -> Result<HashMap<i64, std::result::Result<String, String>>> {
+    let api_key = api.key()?;
+    let file_ids: BTreeSet<i64> = ids.iter().map(|(_, file_id)| *file_id).collect();
+    let files_req = serde_json::json!({ "fileIds": file_ids });
+    let data: CfFilesResp = cf_post(api, &api_key, "/v1/mods/files", &files_req).await?;
+    let mut out = HashMap::new();
+    for cf_file in data.data {
+        if let Some(url) = cf_file.downloadUrl {
+            out.insert(cf_file.id, Ok(url));
+        }
     }
-    let mods: CfModsResp = serde_json::from_str(&body2)?;
-    if let Some(m) = mods.data.first() {
-        let base = m
-            .links
-            .as_ref()
-            .map(|l| l.websiteUrl.clone())
-            .unwrap_or_default();
+    let missing: Vec<(i64, i64)> = ids
+        .iter()
+        .filter(|(_, file_id)| !out.contains_key(file_id))
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        return Ok(out);
+    }
+    // fallback: manual links via /v1/mods
+    let project_ids: BTreeSet<i64> = missing.iter().map(|(project_id, _)| *project_id).collect();
+    let mods_req = serde_json::json!({ "modIds": project_ids });
+    let mods: CfModsResp = cf_post(api, &api_key, "/v1/mods", &mods_req).await?;
+    let websites: HashMap<i64, String> = mods
+        .data
+        .into_iter()
+        .map(|m| (m.id, m.links.map(|l| l.websiteUrl).unwrap_or_default()))
+        .collect();
+    for (project_id, file_id) in missing {
+        let base = websites.get(&project_id).map(String::as_str).unwrap_or("");
         let url = if base.is_empty() {
             format!("https://www.curseforge.com/projects/{project_id}/files/{file_id}")
         } else {
             format!("{}/files/{file_id}", base.trim_end_matches('/'))
         };
-        return Ok(Err(url));
+        out.insert(file_id, Err(url));
     }
-    Ok(Err(format!(
-        "https://www.curseforge.com/projects/{project_id}/files/{file_id}"
-    )))
+    Ok(out)
 }
 
-// (types are used within task modules; not needed directly here)
+// -------- Modrinth resolution (module scope) --------
+pub const MODRINTH_API_BASE: &str = "https://api.modrinth.com/v2";
 
-pub async fn run(cfg: crate::cli::Cli) -> Result<()> {
-    info!(?cfg, "starting packwiz-installer-rust");
-    // Delegate to task::update to avoid duplication
-    let pack_folder_for_update: PathBuf = cfg
-        .pack_folder
-        .clone()
-        .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")));
-    let opts_for_update = crate::task::update::Options {
-        pack_uri: cfg.pack_uri.clone(),
+#[derive(Debug, Clone, Deserialize)]
+struct MrVersionFile {
+    url: String,
+    #[serde(default)]
+    filename: String,
+    #[serde(default)]
+    primary: bool,
+    #[serde(default)]
+    hashes: std::collections::HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct MrVersion {
+    files: Vec<MrVersionFile>,
+}
+
+/// Looks up a version on the Modrinth API and picks the file matching the
+/// expected hash, falling back to the filename and then the primary file.
+pub(crate) async fn mr_get_download_url(
+    api_base: &str,
+    version_id: &str,
+    filename: &str,
+    hash_format: &str,
+    hash: &str,
+) -> Result<Option<String>> {
+    let url = format!("{}/version/{version_id}", api_base.trim_end_matches('/'));
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+    let resp = send_api(Method::GET, &url, headers, None).await?;
+    let status = resp.status;
+    let body = String::from_utf8_lossy(&resp.body);
+    if !status.is_success() {
+        anyhow::bail!("modrinth version api error {}: {}", status, body);
+    }
+    let version: MrVersion = serde_json::from_str(&body)?;
+    let hash_format = hash_format.to_ascii_lowercase();
+    let file = version
+        .files
+        .iter()
+        .find(|f| {
+            f.hashes
+                .get(&hash_format)
+                .is_some_and(|h| h.eq_ignore_ascii_case(hash))
+        })
+        .or_else(|| version.files.iter().find(|f| f.filename == filename))
+        .or_else(|| version.files.iter().find(|f| f.primary))
+        .or_else(|| version.files.first());
+    Ok(file.map(|f| f.url.clone()))
+}
+
+pub use request::fetcher::Fetcher;
+pub use task::report::{EXIT_HASH_MISMATCH, EXIT_MANUAL_DOWNLOAD, EXIT_NETWORK, EXIT_POLICY};
+pub use task::update::{LoadedPack, load_pack};
+
+/// Maps a failed run onto the process exit code
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    task::report::ErrorKind::of(err).exit_code()
+}
+
+/// Builds the update options for the install-style subcommands
+fn update_options(
+    cfg: &cli::InstallArgs,
+    report: &task::report::Reporter,
+) -> Result<task::update::Options> {
+    request::client::configure(&cfg.network)?;
+    let mut option_selection = match cfg.options_file.as_deref() {
+        Some(path) => task::optional::OptionSelection::load(path)?,
+        None => task::optional::OptionSelection::default(),
+    };
+    option_selection.merge(task::optional::OptionSelection {
+        enable: cfg.enable.clone(),
+        disable: cfg.disable.clone(),
+    });
+    // Only ask about optional mods when the user hasn't already decided on the command line
+    let prompt_timeout = (matches!(cfg.optional_mode, crate::cli::OptionalMode::Default)
+        && option_selection.enable.is_empty()
+        && option_selection.disable.is_empty())
+    .then(|| Duration::from_secs(cfg.timeout_secs));
+    Ok(crate::task::update::Options {
+        pack_uri: cfg.pack_uri().to_string(),
         side: cfg.side,
         optional_mode: cfg.optional_mode,
-        pack_folder: pack_folder_for_update,
-        meta_file: cfg.meta_file.clone(),
+        option_selection,
+        prompt_timeout,
+        pack_folder: cfg.folder.pack_folder(),
+        multimc_folder: cfg.multimc_folder.clone(),
+        meta_file: cfg.folder.meta_file.clone(),
+        modrinth_api_base: cfg.modrinth_api_url.clone(),
+        curseforge: CurseForgeApi {
+            base_url: cfg.curseforge_api_url.clone(),
+            api_key: cfg.curseforge_api_key.clone(),
+            api_key_file: cfg.curseforge_api_key_file.clone(),
+            user_agent: cfg.user_agent.clone(),
+        },
+        downloads_dir: cfg.downloads_dir.clone(),
+        manual_wait: Duration::from_secs(cfg.manual_download_wait),
+        loader: cfg.install_loader.then(|| task::loader::LoaderRepos {
+            fabric_meta: cfg.fabric_meta_url.clone(),
+            forge_maven: cfg.forge_maven_url.clone(),
+            neoforge_maven: cfg.neoforge_maven_url.clone(),
+            java: cfg.java.clone(),
+        }),
+        concurrency: cfg.concurrency.into(),
+        cache_dir: cfg.cache_dir(),
+        offline: cfg.offline,
+        report: report.clone(),
+    })
+}
+
+pub async fn run(cfg: crate::cli::Cli) -> Result<()> {
+    use crate::cli::Command;
+    info!(cfg = ?cfg.redacted(), "starting packwiz-installer-rust");
+    for warning in &cfg.config.warnings {
+        warn!("{warning}");
+    }
+    request::auth::configure(cfg.auth.clone());
+    // The legacy flat invocation is the same as `install`
+    let command = cfg.command.unwrap_or(Command::Install(cfg.install));
+    let name = match &command {
+        Command::Install(_) => "install",
+        Command::Update(_) => "update",
+        Command::Verify(_) => "verify",
+        Command::Plan(_) => "plan",
+        Command::Clean(_) => "clean",
+        Command::Info(_) => "info",
+        Command::Bundle(_) => "bundle",
+        Command::Export(_) => "export",
+        Command::Config(_) => "config",
     };
-    crate::task::update::run_update(opts_for_update).await
+    let report = task::report::Reporter::new(cfg.output, name);
+    let result = run_command(command, &report, cfg.output, &cfg.config, &cfg.auth).await;
+    report.finish(&result);
+    result
+}
+
+async fn run_command(
+    command: cli::Command,
+    report: &task::report::Reporter,
+    output: cli::OutputFormat,
+    sources: &config::ConfigSources,
+    auth: &[request::auth::HostAuth],
+) -> Result<()> {
+    use crate::cli::Command;
+    match command {
+        Command::Install(args) => {
+            crate::task::update::run_update(update_options(&args, report)?).await
+        }
+        Command::Update(args) => {
+            let manifest = args.folder.pack_folder().join(&args.folder.meta_file);
+            if !manifest.exists() {
+                anyhow::bail!(
+                    "nothing to update: {} does not exist; use `install` first",
+                    manifest.display()
+                );
+            }
+            crate::task::update::run_update(update_options(&args, report)?).await
+        }
+        Command::Plan(args) => {
+            if args.offline {
+                anyhow::bail!("plan has to fetch the pack, so it can't run with --offline");
+            }
+            let mut opts = update_options(&args, report)?;
+            opts.prompt_timeout = None;
+            crate::task::plan::run_plan(opts).await
+        }
+        Command::Verify(args) => {
+            crate::task::verify::run_verify(&args.pack_folder(), &args.meta_file, report)
+        }
+        Command::Clean(args) => {
+            crate::task::cache::clean(&args.pack_folder(), &args.meta_file, report)
+        }
+        Command::Info(args) => {
+            request::client::configure(&args.network)?;
+            crate::task::info::run_info(&args.pack_uri, report).await
+        }
+        Command::Bundle(args) => {
+            if args.install.offline {
+                anyhow::bail!("bundle has to fetch the pack, so it can't run with --offline");
+            }
+            let opts = update_options(&args.install, report)?;
+            crate::task::bundle::run_bundle(opts, &args.archive).await
+        }
+        Command::Export(args) => {
+            if args.install.offline {
+                anyhow::bail!("export has to fetch the pack, so it can't run with --offline");
+            }
+            let mut opts = update_options(&args.install, report)?;
+            opts.prompt_timeout = None;
+            crate::task::export::run_export(opts, &args.archive, args.format).await
+        }
+        Command::Config(cli::ConfigCommand::Show(args)) => {
+            report.config(config::show(&args, output, sources, auth)?);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::test_server::{Response, TestServer};
+    use std::collections::HashMap;
+
+    fn api(server: &TestServer) -> CurseForgeApi {
+        CurseForgeApi {
+            base_url: server.url(""),
+            api_key: Some(Secret::new("test-key")),
+            api_key_file: None,
+            user_agent: "test".into(),
+        }
+    }
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "packwiz-installer-test-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn cf_api_key_order() {
+        let cli = Secret::new("cli");
+        let cli_file = temp_file("cli-key", "cli-file\n");
+        let env_file = temp_file("env-key", "env-file\n");
+        let mut env = HashMap::from([
+            ("CF_API_KEY", std::ffi::OsString::from("env")),
+            ("CF_API_KEY_FILE", env_file.clone().into_os_string()),
+        ]);
+        let pick = |cli_key, cli_key_file, env: &HashMap<&str, std::ffi::OsString>| {
+            let key = pick_cf_api_key(cli_key, cli_key_file, |name| env.get(name).cloned());
+            key.unwrap().expose().to_string()
+        };
+        assert_eq!(pick(Some(&cli), Some(&cli_file), &env), "cli");
+        assert_eq!(pick(None, Some(&cli_file), &env), "cli-file");
+        assert_eq!(pick(None, None, &env), "env");
+        env.remove("CF_API_KEY");
+        assert_eq!(pick(None, None, &env), "env-file");
+        env.remove("CF_API_KEY_FILE");
+        assert!(!pick(None, None, &env).is_empty());
+        std::fs::remove_file(cli_file).unwrap();
+        std::fs::remove_file(env_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cf_download_urls_fall_back_to_manual_links() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/v1/mods/files" => Response::json(&serde_json::json!({ "data": [
+                { "id": 100, "modId": 10, "downloadUrl": "https://edge.forgecdn.net/a.jar" },
+                { "id": 200, "modId": 20, "downloadUrl": null },
+            ]})),
+            "/v1/mods" => Response::json(&serde_json::json!({ "data": [
+                { "id": 20, "links": { "websiteUrl": "https://www.curseforge.com/minecraft/mc-mods/b/" } },
+            ]})),
+            _ => Response::new(404, ""),
+        });
+        let urls = cf_get_download_urls(&api(&server), &[(10, 100), (20, 200), (30, 300)])
+            .await
+            .unwrap();
+        assert_eq!(urls[&100], Ok("https://edge.forgecdn.net/a.jar".into()));
+        assert_eq!(
+            urls[&200],
+            Err("https://www.curseforge.com/minecraft/mc-mods/b/files/200".into())
+        );
+        assert_eq!(
+            urls[&300],
+            Err("https://www.curseforge.com/projects/30/files/300".into())
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for req in &requests {
+            assert_eq!(req.method, "POST");
+            assert_eq!(req.headers["x-api-key"], "test-key");
+            assert_eq!(req.headers["user-agent"], "test");
+        }
+        let files: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(files, serde_json::json!({ "fileIds": [100, 200, 300] }));
+        let mods: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(mods, serde_json::json!({ "modIds": [20, 30] }));
+    }
+
+    #[tokio::test]
+    async fn cf_post_waits_out_rate_limits() {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let server = TestServer::start(move |_| {
+            if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                let mut resp = Response::new(429, "slow down");
+                resp.headers.push(("Retry-After".into(), "0".into()));
+                resp
+            } else {
+                Response::json(&serde_json::json!({ "data": [
+                    { "id": 100, "modId": 10, "downloadUrl": "https://edge.forgecdn.net/a.jar" },
+                ]}))
+            }
+        });
+        let urls = cf_get_download_urls(&api(&server), &[(10, 100)])
+            .await
+            .unwrap();
+        assert_eq!(urls[&100], Ok("https://edge.forgecdn.net/a.jar".into()));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn cf_post_reports_a_rejected_key() {
+        let server = TestServer::start(|_| Response::new(403, "bad key"));
+        let err = cf_get_download_urls(&api(&server), &[(10, 100)])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RequestError>(),
+            Some(RequestError::Forbidden { body, .. }) if body == "bad key"
+        ));
+        assert_eq!(exit_code(&err), EXIT_NETWORK);
+    }
+
+    #[tokio::test]
+    async fn mr_download_url_fallbacks() {
+        let server = TestServer::start(|req| {
+            let file = |name: &str, primary: bool, sha1: &str| {
+                serde_json::json!({
+                    "url": format!("https://cdn.modrinth.com/{name}"),
+                    "filename": name,
+                    "primary": primary,
+                    "hashes": { "sha1": sha1, "sha512": "ffff" },
+                })
+            };
+            let files = match req.path.as_str() {
+                "/version/v1" => vec![
+                    file("a.jar", false, "aaaa"),
+                    file("b.jar", true, "bbbb"),
+                    file("c.jar", false, "cccc"),
+                ],
+                "/version/v2" => vec![file("a.jar", false, "aaaa"), file("b.jar", false, "bbbb")],
+                "/version/empty" => vec![],
+                _ => return Response::new(404, "not found"),
+            };
+            Response::json(&serde_json::json!({ "files": files }))
+        });
+        let base = server.url("");
+        let url = |version: &'static str, filename: &'static str, hash: &'static str| {
+            let base = base.clone();
+            async move { mr_get_download_url(&base, version, filename, "SHA1", hash).await }
+        };
+        let cdn = |name: &str| Some(format!("https://cdn.modrinth.com/{name}"));
+
+        // the hash wins over the filename, and is compared case-insensitively
+        assert_eq!(url("v1", "a.jar", "CCCC").await.unwrap(), cdn("c.jar"));
+        assert_eq!(url("v1", "c.jar", "0000").await.unwrap(), cdn("c.jar"));
+        assert_eq!(url("v1", "x.jar", "0000").await.unwrap(), cdn("b.jar"));
+        assert_eq!(url("v2", "x.jar", "0000").await.unwrap(), cdn("a.jar"));
+        assert_eq!(url("empty", "x.jar", "0000").await.unwrap(), None);
+        assert!(url("missing", "x.jar", "0000").await.is_err());
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+    }
 }
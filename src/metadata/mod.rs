@@ -1,4 +1,5 @@
 pub mod index;
 pub mod manifest;
 pub mod modfile;
+pub mod mrpack;
 pub mod pack;
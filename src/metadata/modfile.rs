@@ -22,6 +22,15 @@ pub struct ModOption {
     pub default_value: bool,
     #[serde(default)]
     pub description: String,
+    /// Optional mods sharing a group are alternatives; at most one may be enabled
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Mods (by name or metafile path) that must not be enabled alongside this one
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    /// Mods (by name or metafile path) that must be enabled with this one
+    #[serde(default)]
+    pub requires: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -32,12 +41,17 @@ pub struct ModDownload {
     pub hash: String,
     #[serde(default)]
     pub mode: DownloadMode,
+    /// Other URLs serving the same file, tried in order when `url` fails (not part of packwiz)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Default)]
 pub enum DownloadMode {
     #[default]
+    #[serde(rename = "url")]
     Url,
+    #[serde(rename = "metadata:curseforge")]
     Curseforge,
 }
 
@@ -72,6 +86,8 @@ impl<'de> Deserialize<'de> for DownloadMode {
 pub struct ModUpdate {
     #[serde(default)]
     pub curseforge: Option<CfUpdate>,
+    #[serde(default)]
+    pub modrinth: Option<MrUpdate>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -81,3 +97,10 @@ pub struct CfUpdate {
     #[serde(rename = "file-id")]
     pub file_id: i64,
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct MrUpdate {
+    #[serde(rename = "mod-id")]
+    pub mod_id: String,
+    pub version: String,
+}
@@ -0,0 +1,542 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::destination::side::Side;
+use crate::metadata::index::{IndexEntry, IndexToml};
+use crate::metadata::modfile::{DownloadMode, ModDownload, ModOption, ModToml, ModUpdate};
+use crate::metadata::pack::{IndexFileLoc, Loader, PackFile, PackVersions};
+
+/// Manifest at the root of a Modrinth modpack (.mrpack)
+pub const INDEX_FILE: &str = "modrinth.index.json";
+
+/// Folder the generated metafiles of an imported .mrpack are served from
+const METAFILE_DIR: &str = ".mrpack";
+
+/// Hosts the .mrpack format allows files to be downloaded from
+pub const ALLOWED_HOSTS: [&str; 4] = [
+    "cdn.modrinth.com",
+    "github.com",
+    "raw.githubusercontent.com",
+    "gitlab.com",
+];
+
+/// Whether a .mrpack may download from `url`: HTTPS on one of [`ALLOWED_HOSTS`]
+pub fn allowed_download(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|u| {
+        u.scheme() == "https" && u.host_str().is_some_and(|h| ALLOWED_HOSTS.contains(&h))
+    })
+}
+
+/// `modrinth.index.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MrIndex {
+    pub format_version: u32,
+    pub game: String,
+    pub version_id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    pub files: Vec<MrFile>,
+    pub dependencies: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MrFile {
+    pub path: String,
+    pub hashes: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<MrEnv>,
+    pub downloads: Vec<String>,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MrEnv {
+    pub client: EnvSupport,
+    pub server: EnvSupport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvSupport {
+    Required,
+    Optional,
+    Unsupported,
+}
+
+impl MrEnv {
+    /// Required on the sides a packwiz mod is installed on
+    pub fn for_side(side: Side) -> Self {
+        use EnvSupport::*;
+        let (client, server) = match side {
+            Side::Client => (Required, Unsupported),
+            Side::Server => (Unsupported, Required),
+            Side::Both => (Required, Required),
+        };
+        MrEnv { client, server }
+    }
+
+    /// The sides the file is installed on; `None` when it's unsupported on both
+    pub fn side(self) -> Option<Side> {
+        match (self.client, self.server) {
+            (EnvSupport::Unsupported, EnvSupport::Unsupported) => None,
+            (_, EnvSupport::Unsupported) => Some(Side::Client),
+            (EnvSupport::Unsupported, _) => Some(Side::Server),
+            _ => Some(Side::Both),
+        }
+    }
+
+    /// Whether the file is optional wherever it's installed
+    pub fn optional(self) -> bool {
+        ![self.client, self.server].contains(&EnvSupport::Required)
+            && [self.client, self.server].contains(&EnvSupport::Optional)
+    }
+}
+
+/// Sources of one installed path among an .mrpack's override folders
+#[derive(Default)]
+struct Overrides<'a> {
+    both: Option<&'a str>,
+    client: Option<&'a str>,
+    server: Option<&'a str>,
+}
+
+/// Builds a packwiz pack (`pack.toml`, `index.toml` and metafiles) from the
+/// files of an .mrpack, so it installs like any other pack. Files listed in
+/// `modrinth.index.json` become metafiles on the sides their `env` allows;
+/// `overrides/` become plain files, and `client-overrides/` and
+/// `server-overrides/` become metafiles pointing back into the archive.
+pub fn to_packwiz(files: &HashMap<String, Bytes>) -> Result<Vec<(String, Bytes)>> {
+    let data = files
+        .get(INDEX_FILE)
+        .with_context(|| format!("no {INDEX_FILE} in the modpack"))?;
+    let index: MrIndex =
+        serde_json::from_slice(data).with_context(|| format!("failed to parse {INDEX_FILE}"))?;
+    if index.game != "minecraft" {
+        anyhow::bail!("the modpack is for {}, not minecraft", index.game);
+    }
+    if index.format_version != 1 {
+        anyhow::bail!(
+            "unsupported {INDEX_FILE} formatVersion {}",
+            index.format_version
+        );
+    }
+
+    let mut out = Vec::new();
+    let mut entries = Vec::new();
+
+    for file in &index.files {
+        check_path(&file.path)?;
+        let env = file.env.unwrap_or(MrEnv::for_side(Side::Both));
+        let Some(side) = env.side() else {
+            tracing::warn!("skipping {}: it is unsupported on both sides", file.path);
+            continue;
+        };
+        let (hash_format, hash) = ["sha512", "sha1"]
+            .into_iter()
+            .find_map(|f| file.hashes.get(f).map(|h| (f, h)))
+            .with_context(|| format!("{} has no sha512 or sha1 hash", file.path))?;
+        let (allowed, rejected): (Vec<&String>, Vec<&String>) =
+            file.downloads.iter().partition(|u| allowed_download(u));
+        for url in rejected {
+            tracing::warn!(
+                "ignoring {url} for {}: not an allowed download host",
+                file.path
+            );
+        }
+        let Some((url, mirrors)) = allowed.split_first() else {
+            anyhow::bail!("{} has no download URL on an allowed host", file.path);
+        };
+        let filename = file_name(&file.path);
+        let mod_toml = ModToml {
+            name: filename.to_string(),
+            filename: filename.to_string(),
+            side,
+            download: ModDownload {
+                url: Some(url.to_string()),
+                hash_format: hash_format.to_string(),
+                hash: hash.to_lowercase(),
+                mode: DownloadMode::Url,
+                mirrors: mirrors.iter().map(|u| u.to_string()).collect(),
+            },
+            option: ModOption {
+                optional: env.optional(),
+                default_value: true,
+                ..Default::default()
+            },
+            update: ModUpdate::default(),
+        };
+        let path = format!("{METAFILE_DIR}/{}.pw.toml", file.path);
+        let (entry, data) = metafile(&path, &file.path, &mod_toml)?;
+        entries.push(entry);
+        out.push((path, data));
+    }
+
+    let mut overrides: BTreeMap<&str, Overrides> = BTreeMap::new();
+    for path in files.keys() {
+        let Some((folder, dest)) = path.split_once('/') else {
+            continue;
+        };
+        if !matches!(
+            folder,
+            "overrides" | "client-overrides" | "server-overrides"
+        ) {
+            continue;
+        }
+        check_path(dest)?;
+        let o = overrides.entry(dest).or_default();
+        let slot = match folder {
+            "overrides" => &mut o.both,
+            "client-overrides" => &mut o.client,
+            _ => &mut o.server,
+        };
+        *slot = Some(path);
+    }
+    for (dest, o) in overrides {
+        // A side's own override replaces the common one on that side
+        let client = o.client.or(o.both);
+        let server = o.server.or(o.both);
+        if client == server
+            && let Some(source) = client
+        {
+            entries.push(IndexEntry {
+                file: encode_path(source),
+                hash_format: None,
+                hash: crate::sha256_hex(&files[source]),
+                alias: Some(dest.to_string()),
+                metafile: false,
+                preserve: false,
+            });
+            continue;
+        }
+        for (side, source) in [(Side::Client, client), (Side::Server, server)] {
+            let Some(source) = source else {
+                continue;
+            };
+            let path = format!("{METAFILE_DIR}/{}/{dest}.pw.toml", side_name(side));
+            let up = "../".repeat(path.matches('/').count());
+            let mod_toml = ModToml {
+                name: dest.to_string(),
+                filename: file_name(dest).to_string(),
+                side,
+                download: ModDownload {
+                    url: Some(format!("{up}{}", encode_path(source))),
+                    hash_format: "sha256".into(),
+                    hash: crate::sha256_hex(&files[source]),
+                    mode: DownloadMode::Url,
+                    mirrors: Vec::new(),
+                },
+                option: ModOption::default(),
+                update: ModUpdate::default(),
+            };
+            let (entry, data) = metafile(&path, dest, &mod_toml)?;
+            entries.push(entry);
+            out.push((path, data));
+        }
+    }
+
+    let index_toml = toml::to_string(&IndexToml {
+        hash_format: "sha256".into(),
+        files: entries,
+    })?;
+    let mut versions = PackVersions {
+        minecraft: index.dependencies.get("minecraft").cloned(),
+        ..Default::default()
+    };
+    for (key, version) in &index.dependencies {
+        let slot = match Loader::ALL.into_iter().find(|l| l.modrinth_key() == key) {
+            Some(Loader::Forge) => &mut versions.forge,
+            Some(Loader::NeoForge) => &mut versions.neoforge,
+            Some(Loader::Fabric) => &mut versions.fabric,
+            Some(Loader::Quilt) => &mut versions.quilt,
+            None if key == "minecraft" => continue,
+            None => {
+                versions.other.insert(key.clone(), version.clone().into());
+                continue;
+            }
+        };
+        *slot = Some(version.clone());
+    }
+    let pack = PackFile {
+        name: Some(index.name),
+        version: Some(index.version_id),
+        author: None,
+        description: index.summary,
+        pack_format: Some("packwiz:1.1.0".into()),
+        index: Some(IndexFileLoc {
+            file: "index.toml".into(),
+            hash_format: Some("sha256".into()),
+            hash: Some(crate::sha256_hex(index_toml.as_bytes())),
+        }),
+        versions,
+    };
+    out.push(("index.toml".into(), Bytes::from(index_toml)));
+    out.push(("pack.toml".into(), Bytes::from(toml::to_string(&pack)?)));
+    Ok(out)
+}
+
+/// A generated metafile at `path`, installed at `dest`, and its index entry
+fn metafile(path: &str, dest: &str, mod_toml: &ModToml) -> Result<(IndexEntry, Bytes)> {
+    let data = toml::to_string(mod_toml)?;
+    // An alias without a folder would be installed in mods/
+    let alias = if dest.contains('/') {
+        dest.to_string()
+    } else {
+        format!("./{dest}")
+    };
+    let entry = IndexEntry {
+        file: encode_path(path),
+        hash_format: None,
+        hash: crate::sha256_hex(data.as_bytes()),
+        alias: Some(alias),
+        metafile: true,
+        preserve: false,
+    };
+    Ok((entry, Bytes::from(data)))
+}
+
+/// Rejects paths that would install outside the pack folder
+fn check_path(path: &str) -> Result<()> {
+    if path.is_empty()
+        || path.starts_with('/')
+        || path.contains('\\')
+        || path.contains(':')
+        || path.split('/').any(|s| s == ".." || s == ".")
+    {
+        anyhow::bail!("unsafe path in the modpack: {path}");
+    }
+    Ok(())
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Client => "client",
+        Side::Server => "server",
+        Side::Both => "both",
+    }
+}
+
+/// Percent-encodes `path` the way URL paths are, so it can be joined to the
+/// pack's URL without a `#` or `?` cutting it short
+fn encode_path(path: &str) -> String {
+    let mut out = String::new();
+    for b in path.bytes() {
+        if b.is_ascii_graphic() && !b"\"#<>?`{}".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn mr_file(path: &str, env: Option<(&str, &str)>, downloads: &[&str]) -> serde_json::Value {
+        let mut file = serde_json::json!({
+            "path": path,
+            "hashes": { "sha1": HASH },
+            "downloads": downloads,
+            "fileSize": 1,
+        });
+        if let Some((client, server)) = env {
+            file["env"] = serde_json::json!({ "client": client, "server": server });
+        }
+        file
+    }
+
+    /// An .mrpack's files, with `files` listed in its index
+    fn mrpack(files: Vec<serde_json::Value>, extra: &[(&str, &str)]) -> HashMap<String, Bytes> {
+        let index = serde_json::json!({
+            "formatVersion": 1,
+            "game": "minecraft",
+            "versionId": "1.0.0",
+            "name": "Test",
+            "files": files,
+            "dependencies": { "minecraft": "1.21.1", "fabric-loader": "0.16.0" },
+        });
+        let mut out = HashMap::from([(INDEX_FILE.to_string(), Bytes::from(index.to_string()))]);
+        for (path, data) in extra {
+            out.insert(path.to_string(), Bytes::from(data.to_string()));
+        }
+        out
+    }
+
+    fn import(files: &HashMap<String, Bytes>) -> (IndexToml, HashMap<String, Bytes>) {
+        let out: HashMap<String, Bytes> = to_packwiz(files).unwrap().into_iter().collect();
+        let index = toml::from_str(std::str::from_utf8(&out["index.toml"]).unwrap()).unwrap();
+        (index, out)
+    }
+
+    fn metafile(out: &HashMap<String, Bytes>, path: &str) -> ModToml {
+        toml::from_str(std::str::from_utf8(&out[path]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_pack() {
+        for path in [
+            "../evil.jar",
+            "mods/../../evil.jar",
+            "/etc/evil",
+            "C:/evil.jar",
+            "mods\\evil.jar",
+            "./mods/a.jar",
+            "",
+        ] {
+            assert!(check_path(path).is_err(), "{path}");
+        }
+        assert!(check_path("mods/a.jar").is_ok());
+
+        let cdn = ["https://cdn.modrinth.com/data/a.jar"];
+        let files = mrpack(vec![mr_file("../evil.jar", None, &cdn)], &[]);
+        let err = to_packwiz(&files).unwrap_err();
+        assert!(err.to_string().contains("unsafe path"), "{err}");
+        let files = mrpack(vec![], &[("overrides/../evil.txt", "x")]);
+        assert!(to_packwiz(&files).is_err());
+    }
+
+    #[test]
+    fn only_downloads_from_allowed_hosts() {
+        assert!(allowed_download("https://cdn.modrinth.com/data/a.jar"));
+        assert!(allowed_download(
+            "https://github.com/o/r/releases/download/v1/a.jar"
+        ));
+        assert!(!allowed_download("http://cdn.modrinth.com/data/a.jar"));
+        assert!(!allowed_download("https://cdn.modrinth.com.evil.com/a.jar"));
+        assert!(!allowed_download("https://evil.com/a.jar"));
+        assert!(!allowed_download("not a url"));
+
+        let files = mrpack(
+            vec![mr_file(
+                "mods/a.jar",
+                None,
+                &[
+                    "https://evil.com/a.jar",
+                    "https://cdn.modrinth.com/a.jar",
+                    "https://github.com/a.jar",
+                ],
+            )],
+            &[],
+        );
+        let (_, out) = import(&files);
+        let download = metafile(&out, ".mrpack/mods/a.jar.pw.toml").download;
+        assert_eq!(
+            download.url.as_deref(),
+            Some("https://cdn.modrinth.com/a.jar")
+        );
+        assert_eq!(download.mirrors, ["https://github.com/a.jar"]);
+        assert_eq!(download.hash, HASH);
+
+        let files = mrpack(
+            vec![mr_file("mods/a.jar", None, &["https://evil.com/a.jar"])],
+            &[],
+        );
+        let err = to_packwiz(&files).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no download URL on an allowed host"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn maps_env_to_side_and_optional() {
+        use EnvSupport::*;
+        let env = |client, server| MrEnv { client, server };
+        assert_eq!(env(Required, Required).side(), Some(Side::Both));
+        assert_eq!(env(Required, Unsupported).side(), Some(Side::Client));
+        assert_eq!(env(Unsupported, Optional).side(), Some(Side::Server));
+        assert_eq!(env(Unsupported, Unsupported).side(), None);
+        assert!(!env(Required, Optional).optional());
+        assert!(env(Optional, Unsupported).optional());
+        assert!(env(Optional, Optional).optional());
+        assert!(!env(Unsupported, Unsupported).optional());
+
+        let cdn = ["https://cdn.modrinth.com/a.jar"];
+        let files = mrpack(
+            vec![
+                mr_file("mods/both.jar", None, &cdn),
+                mr_file("mods/client.jar", Some(("required", "unsupported")), &cdn),
+                mr_file("mods/server.jar", Some(("unsupported", "optional")), &cdn),
+                mr_file("mods/none.jar", Some(("unsupported", "unsupported")), &cdn),
+            ],
+            &[],
+        );
+        let (index, out) = import(&files);
+        let side = |name: &str| {
+            let m = metafile(&out, &format!(".mrpack/mods/{name}.jar.pw.toml"));
+            (m.side, m.option.optional)
+        };
+        assert_eq!(side("both"), (Side::Both, false));
+        assert_eq!(side("client"), (Side::Client, false));
+        assert_eq!(side("server"), (Side::Server, true));
+        assert!(!out.contains_key(".mrpack/mods/none.jar.pw.toml"));
+        assert_eq!(index.files.len(), 3);
+        assert!(index.files.iter().all(|e| e.metafile));
+        assert_eq!(index.files[0].alias.as_deref(), Some("mods/both.jar"));
+    }
+
+    #[test]
+    fn side_overrides_win_over_common_ones() {
+        let files = mrpack(
+            vec![],
+            &[
+                ("overrides/config/a.txt", "common"),
+                ("client-overrides/config/a.txt", "client"),
+                ("overrides/config/b.txt", "common"),
+                ("server-overrides/options.txt", "server"),
+            ],
+        );
+        let (index, out) = import(&files);
+        let entry = |alias: &str| {
+            index
+                .files
+                .iter()
+                .filter(|e| e.alias.as_deref() == Some(alias))
+                .collect::<Vec<_>>()
+        };
+
+        // the same file on both sides stays a plain file read from the archive
+        let b = entry("config/b.txt");
+        assert_eq!(b.len(), 1);
+        assert!(!b[0].metafile);
+        assert_eq!(b[0].file, "overrides/config/b.txt");
+
+        // a client override replaces the common file on the client only
+        assert_eq!(entry("config/a.txt").len(), 2);
+        let client = metafile(&out, ".mrpack/client/config/a.txt.pw.toml");
+        assert_eq!(client.side, Side::Client);
+        assert_eq!(
+            client.download.url.as_deref(),
+            Some("../../../client-overrides/config/a.txt")
+        );
+        assert_eq!(client.download.hash, crate::sha256_hex(b"client"));
+        let server = metafile(&out, ".mrpack/server/config/a.txt.pw.toml");
+        assert_eq!(server.side, Side::Server);
+        assert_eq!(
+            server.download.url.as_deref(),
+            Some("../../../overrides/config/a.txt")
+        );
+
+        // a file only the server has isn't installed on the client, and
+        // keeps out of mods/ despite having no folder
+        let options = entry("./options.txt");
+        assert_eq!(options.len(), 1);
+        assert_eq!(
+            metafile(&out, ".mrpack/server/options.txt.pw.toml").side,
+            Side::Server
+        );
+    }
+}
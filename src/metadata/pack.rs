@@ -1,13 +1,171 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::errors::PolicyViolation;
+
+/// Major pack-format version this installer understands (`packwiz:1.x.y`)
+pub const SUPPORTED_PACK_FORMAT_MAJOR: u64 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackFile {
     pub name: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     #[serde(rename = "pack-format")]
-    pub pack_format: Option<serde_json::Value>,
+    pub pack_format: Option<String>,
     pub index: Option<IndexFileLoc>,
     #[serde(default)]
-    pub versions: serde_json::Map<String, serde_json::Value>,
+    pub versions: PackVersions,
+}
+
+impl PackFile {
+    /// Checks that the pack-format is one we support and that `[versions]` is usable
+    pub fn validate(&self) -> Result<()> {
+        // packwiz treats a missing pack-format as the first release
+        let format = self.pack_format.as_deref().unwrap_or("packwiz:1.0.0");
+        let version = format
+            .strip_prefix("packwiz:")
+            .ok_or_else(|| anyhow::anyhow!("unsupported pack-format \"{format}\""))?;
+        let major = version
+            .split('.')
+            .next()
+            .and_then(|m| m.parse::<u64>().ok())
+            .ok_or_else(|| anyhow::anyhow!("invalid pack-format version \"{format}\""))?;
+        if major != SUPPORTED_PACK_FORMAT_MAJOR {
+            return Err(PolicyViolation(format!(
+                "unsupported pack-format \"{}\": this installer supports packwiz:{}.x; try a newer installer",
+                format,
+                SUPPORTED_PACK_FORMAT_MAJOR
+            ))
+            .into());
+        }
+        self.versions.validate()
+    }
+}
+
+/// The `[versions]` table: the Minecraft version and the mod loaders the pack needs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PackVersions {
+    pub minecraft: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forge: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub neoforge: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fabric: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quilt: Option<String>,
+    /// Entries we don't model (e.g. liteloader), kept as-is
+    #[serde(flatten)]
+    pub other: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loader {
+    Forge,
+    NeoForge,
+    Fabric,
+    Quilt,
+}
+
+impl Loader {
+    pub const ALL: [Loader; 4] = [
+        Loader::Forge,
+        Loader::NeoForge,
+        Loader::Fabric,
+        Loader::Quilt,
+    ];
+
+    /// Key in the `[versions]` table
+    pub fn key(self) -> &'static str {
+        match self {
+            Loader::Forge => "forge",
+            Loader::NeoForge => "neoforge",
+            Loader::Fabric => "fabric",
+            Loader::Quilt => "quilt",
+        }
+    }
+
+    /// Key in the `dependencies` of a Modrinth modpack
+    pub fn modrinth_key(self) -> &'static str {
+        match self {
+            Loader::Forge => "forge",
+            Loader::NeoForge => "neoforge",
+            Loader::Fabric => "fabric-loader",
+            Loader::Quilt => "quilt-loader",
+        }
+    }
+
+    /// Component UID used by MultiMC/Prism in `mmc-pack.json`
+    pub fn mmc_uid(self) -> &'static str {
+        match self {
+            Loader::Forge => "net.minecraftforge",
+            Loader::NeoForge => "net.neoforged",
+            Loader::Fabric => "net.fabricmc.fabric-loader",
+            Loader::Quilt => "org.quiltmc.quilt-loader",
+        }
+    }
+}
+
+impl PackVersions {
+    pub fn minecraft(&self) -> Option<&str> {
+        self.minecraft.as_deref()
+    }
+
+    pub fn loader_version(&self, loader: Loader) -> Option<&str> {
+        match loader {
+            Loader::Forge => self.forge.as_deref(),
+            Loader::NeoForge => self.neoforge.as_deref(),
+            Loader::Fabric => self.fabric.as_deref(),
+            Loader::Quilt => self.quilt.as_deref(),
+        }
+    }
+
+    /// Every supported loader the pack declares, with its version
+    pub fn loaders(&self) -> Vec<(Loader, &str)> {
+        Loader::ALL
+            .into_iter()
+            .filter_map(|l| self.loader_version(l).map(|v| (l, v)))
+            .collect()
+    }
+
+    /// The pack's main loader, if any. When several are listed Quilt wins,
+    /// since it also runs Fabric mods.
+    pub fn loader(&self) -> Option<(Loader, &str)> {
+        let loaders = self.loaders();
+        loaders
+            .iter()
+            .find(|(l, _)| *l == Loader::Quilt)
+            .or_else(|| loaders.first())
+            .copied()
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.minecraft() {
+            None => anyhow::bail!("pack.toml [versions] is missing the minecraft version"),
+            Some(v) if v.trim().is_empty() => {
+                anyhow::bail!("pack.toml [versions] has an empty minecraft version")
+            }
+            Some(_) => {}
+        }
+        for (loader, version) in self.loaders() {
+            if version.trim().is_empty() {
+                anyhow::bail!("pack.toml [versions] has an empty {} version", loader.key());
+            }
+        }
+        for key in self.other.keys() {
+            tracing::debug!(
+                "pack.toml [versions] has unknown entry \"{}\"; ignoring",
+                key
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,3 +175,71 @@ pub struct IndexFileLoc {
     pub hash_format: Option<String>,
     pub hash: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(format: Option<&str>, versions: &str) -> PackFile {
+        let format = format
+            .map(|f| format!("pack-format = \"{f}\"\n"))
+            .unwrap_or_default();
+        toml::from_str(&format!(
+            "name = \"Test\"\n{format}\n[index]\nfile = \"index.toml\"\n\n[versions]\n{versions}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_known_versions() {
+        let parsed = pack(
+            Some("packwiz:1.1.0"),
+            "minecraft = \"1.21.1\"\nfabric = \"0.16.0\"\nliteloader = \"1.0\"\n",
+        );
+        parsed.validate().unwrap();
+        assert_eq!(parsed.versions.minecraft(), Some("1.21.1"));
+        assert_eq!(parsed.versions.loader(), Some((Loader::Fabric, "0.16.0")));
+        assert!(parsed.versions.other.contains_key("liteloader"));
+        // packs written before pack-format existed are the first format
+        pack(None, "minecraft = \"1.21.1\"\n").validate().unwrap();
+    }
+
+    #[test]
+    fn needs_a_minecraft_version() {
+        let err = pack(None, "fabric = \"0.16.0\"\n").validate().unwrap_err();
+        assert!(
+            err.to_string().contains("missing the minecraft version"),
+            "{err}"
+        );
+        let err = pack(None, "minecraft = \" \"\n").validate().unwrap_err();
+        assert!(err.to_string().contains("empty minecraft version"), "{err}");
+        let err = pack(None, "minecraft = \"1.21.1\"\nquilt = \"\"\n")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("empty quilt version"), "{err}");
+    }
+
+    #[test]
+    fn refuses_unknown_pack_formats() {
+        let err = pack(Some("packwiz:2.0.0"), "minecraft = \"1.21.1\"\n")
+            .validate()
+            .unwrap_err();
+        assert!(err.is::<PolicyViolation>(), "{err}");
+        assert_eq!(crate::exit_code(&err), crate::EXIT_POLICY);
+        for format in ["other:1.0.0", "packwiz:x.1"] {
+            let err = pack(Some(format), "minecraft = \"1.21.1\"\n")
+                .validate()
+                .unwrap_err();
+            assert!(err.to_string().contains(format), "{err}");
+        }
+    }
+
+    #[test]
+    fn quilt_is_the_main_loader() {
+        let versions: PackVersions =
+            toml::from_str("minecraft = \"1.21.1\"\nfabric = \"0.16.0\"\nquilt = \"0.26.0\"\n")
+                .unwrap();
+        assert_eq!(versions.loader(), Some((Loader::Quilt, "0.26.0")));
+        assert_eq!(versions.loaders().len(), 2);
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::Component;
+use std::sync::{Arc, LazyLock, RwLock, Weak};
+
+use crate::metadata::mrpack;
+use crate::request::auth::redact_url;
+use crate::request::fetcher::Fetcher;
+
+/// Scheme of the URLs pointing inside a pack archive opened by [`mount`],
+/// e.g. `pack-archive://<id>/mods/sodium.pw.toml`
+pub const SCHEME: &str = "pack-archive";
+
+/// Contents of an archive by path
+type Files = HashMap<String, Bytes>;
+
+/// Every mounted archive, keyed by its id. The [`Mount`]s own the files, so
+/// an archive is gone once nothing uses it.
+static MOUNTED: LazyLock<RwLock<HashMap<String, Weak<Files>>>> = LazyLock::new(Default::default);
+
+/// An archive opened by [`mount`]; its files can be fetched until this is dropped
+pub struct Mount {
+    id: String,
+    url: String,
+    files: Arc<Files>,
+}
+
+impl Mount {
+    /// The `pack-archive://` URL of the archive's pack.toml
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl std::fmt::Debug for Mount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Mount").field(&self.url).finish()
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let mut mounted = MOUNTED.write().unwrap();
+        // The same archive may have been mounted again since
+        if mounted
+            .get(&self.id)
+            .is_some_and(|files| Weak::ptr_eq(files, &Arc::downgrade(&self.files)))
+        {
+            mounted.remove(&self.id);
+            tracing::debug!("unmounted {}", self.url);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Zip,
+    TarGz,
+    TarZst,
+}
+
+impl Format {
+    fn of(uri: &str) -> Option<Self> {
+        let path = match Url::parse(uri) {
+            Ok(url) if url.scheme() != SCHEME => url.path().to_ascii_lowercase(),
+            Ok(_) => return None,
+            Err(_) => uri.to_ascii_lowercase(),
+        };
+        if path.ends_with(".zip") || path.ends_with(".mrpack") {
+            Some(Format::Zip)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if path.ends_with(".tar.zst") || path.ends_with(".tzst") {
+            Some(Format::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `uri` names a pack archive (.zip, .tar.gz, .tar.zst or .mrpack) instead of a pack.toml
+pub fn is_archive(uri: &str) -> bool {
+    Format::of(uri).is_some()
+}
+
+/// Fetches the archive at `uri` and keeps its files in memory while the
+/// returned [`Mount`] lives. Its URL points to the pack.toml, which is either
+/// at the root or in the archive's only top-level folder, or generated from an
+/// .mrpack's `modrinth.index.json`; paths joined to it resolve inside the archive.
+pub async fn mount(uri: &str) -> Result<Mount> {
+    let format = Format::of(uri).context("not a pack archive")?;
+    let data = crate::fetch_bytes(uri)
+        .await
+        .with_context(|| format!("failed to fetch pack archive: {}", redact_url(uri)))?;
+    let mut files = tokio::task::spawn_blocking(move || extract(format, &data))
+        .await?
+        .with_context(|| format!("failed to read pack archive: {}", redact_url(uri)))?;
+    // A Modrinth modpack is read through a packwiz pack generated from its index
+    if !files.contains_key("pack.toml") && files.contains_key(mrpack::INDEX_FILE) {
+        let generated = mrpack::to_packwiz(&files)
+            .with_context(|| format!("failed to import {}", redact_url(uri)))?;
+        files.extend(generated);
+    }
+    let pack = find_pack(&files)
+        .with_context(|| format!("no pack.toml in {}", redact_url(uri)))?
+        .to_string();
+
+    // Named after the archive's URI, so the pack's cache entries stay the same across runs
+    let id = crate::sha256_hex(uri.as_bytes())[..16].to_string();
+    let mut url = Url::parse(&format!("{SCHEME}://{id}/"))?;
+    let files = files
+        .into_iter()
+        .map(|(path, data)| {
+            url.set_path(&path);
+            (url.path().to_string(), data)
+        })
+        .collect();
+    url.set_path(&pack);
+    let files = Arc::new(files);
+    MOUNTED
+        .write()
+        .unwrap()
+        .insert(id.clone(), Arc::downgrade(&files));
+    tracing::debug!("mounted {} as {url}", redact_url(uri));
+    Ok(Mount {
+        id,
+        url: url.to_string(),
+        files,
+    })
+}
+
+fn extract(format: Format, data: &[u8]) -> Result<Files> {
+    let mut files = HashMap::new();
+    match format {
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(Cursor::new(data))?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                if !file.is_file() {
+                    continue;
+                }
+                let Some(path) = file.enclosed_name() else {
+                    anyhow::bail!("unsafe path in archive: {}", file.name());
+                };
+                let path = path.to_string_lossy().replace('\\', "/");
+                let mut buf = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut buf)?;
+                files.insert(path, Bytes::from(buf));
+            }
+        }
+        Format::TarGz => read_tar(flate2::read::GzDecoder::new(data), &mut files)?,
+        Format::TarZst => read_tar(zstd::Decoder::new(data)?, &mut files)?,
+    }
+    Ok(files)
+}
+
+fn read_tar(reader: impl Read, files: &mut Files) -> Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let raw = entry.path()?.into_owned();
+        let mut parts = Vec::new();
+        for component in raw.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy()),
+                Component::CurDir => {}
+                _ => anyhow::bail!("unsafe path in archive: {}", raw.display()),
+            }
+        }
+        let path = parts.join("/");
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut buf)?;
+        files.insert(path, Bytes::from(buf));
+    }
+    Ok(())
+}
+
+/// `pack.toml`, or `<folder>/pack.toml` when everything is in one folder (as in GitHub's source zips)
+fn find_pack(files: &Files) -> Option<&str> {
+    if let Some((path, _)) = files.get_key_value("pack.toml") {
+        return Some(path);
+    }
+    let mut top = files.keys().map(|p| p.split_once('/').map(|(dir, _)| dir));
+    let dir = top.next()??;
+    if !top.all(|d| d == Some(dir)) {
+        return None;
+    }
+    files
+        .get_key_value(format!("{dir}/pack.toml").as_str())
+        .map(|(path, _)| path.as_str())
+}
+
+/// Serves the files of mounted archives
+pub struct ArchiveFetcher;
+
+impl Fetcher for ArchiveFetcher {
+    fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<Bytes>> {
+        Box::pin(async move {
+            let files = url
+                .host_str()
+                .and_then(|id| MOUNTED.read().unwrap().get(id)?.upgrade())
+                .with_context(|| format!("{url} is not in a mounted archive"))?;
+            files
+                .get(url.path())
+                .cloned()
+                .with_context(|| format!("{} is not in the pack archive", url.path()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch_bytes as fetch;
+    use crate::task::test_pack::{TempDir, TestPack};
+    use std::io::Write;
+
+    fn pack_files(prefix: &str) -> Vec<(String, Bytes)> {
+        TestPack::new()
+            .file("config/a.txt", "a")
+            .files()
+            .into_iter()
+            .map(|(path, data)| (format!("{prefix}{path}"), data))
+            .collect()
+    }
+
+    fn zip(files: &[(String, Bytes)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, data) in files {
+            zip.start_file(path.as_str(), zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// A tar with the names written as given, `..` and absolute ones included
+    fn tar(files: &[(String, Bytes)]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, data.as_ref()).unwrap();
+        }
+        tar.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(data).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn mounts_zips_and_tarballs() {
+        let dir = TempDir::new("archive-mount");
+        let archives = [
+            ("pack.zip", zip(&pack_files("pack-main/"))),
+            ("pack.tar.gz", gzip(&tar(&pack_files("./")))),
+            (
+                "pack.tar.zst",
+                zstd::encode_all(&tar(&pack_files(""))[..], 0).unwrap(),
+            ),
+        ];
+        for (name, data) in archives {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            let mount = mount(path.to_str().unwrap()).await.unwrap();
+            let url = mount.url().to_string();
+            assert!(url.starts_with("pack-archive://"), "{url}");
+            assert!(url.ends_with("/pack.toml"), "{url}");
+            let pack = fetch(&url).await.unwrap();
+            assert!(pack.starts_with(b"name = \"Test\""), "{name}");
+            let config = crate::join_uri(&url, "config/a.txt").unwrap();
+            assert_eq!(fetch(&config).await.unwrap(), "a");
+            let missing = crate::join_uri(&url, "config/b.txt").unwrap();
+            assert!(fetch(&missing).await.is_err());
+
+            drop(mount);
+            let err = fetch(&url).await.unwrap_err();
+            assert!(
+                err.to_string().contains("is not in a mounted archive"),
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_remounted_archives() {
+        let dir = TempDir::new("archive-remount");
+        let path = dir.join("pack.zip");
+        std::fs::write(&path, zip(&pack_files(""))).unwrap();
+        let first = mount(path.to_str().unwrap()).await.unwrap();
+        let second = mount(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(first.url(), second.url());
+        drop(first);
+        assert!(fetch(second.url()).await.is_ok());
+    }
+
+    #[test]
+    fn rejects_tar_paths_outside_the_archive() {
+        for path in ["../evil.txt", "mods/../../evil.txt", "/etc/evil"] {
+            let data = tar(&[(path.to_string(), Bytes::from("x"))]);
+            let err = read_tar(&data[..], &mut Files::new()).unwrap_err();
+            assert!(err.to_string().contains("unsafe path"), "{path}: {err}");
+        }
+        let mut files = Files::new();
+        read_tar(
+            &tar(&[("./mods/a.jar".to_string(), Bytes::from("a"))])[..],
+            &mut files,
+        )
+        .unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["mods/a.jar"]);
+    }
+}
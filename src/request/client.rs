@@ -1,15 +1,315 @@
-use anyhow::Result;
-use reqwest::{Client, ClientBuilder};
+use anyhow::{Context, Result};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, NoProxy, Proxy};
+use std::path::Path;
+use std::sync::RwLock;
 use std::time::Duration;
 
-pub fn build_http_client() -> Result<Client> {
-    let client = ClientBuilder::new()
+use crate::cli::NetworkArgs;
+
+/// Proxy and TLS settings, loaded once and applied to every client built afterwards
+#[derive(Clone, Default)]
+struct Network {
+    /// Tried in order; empty leaves reqwest to read the environment itself
+    proxies: Vec<Proxy>,
+    roots: Vec<Certificate>,
+    identity: Option<Identity>,
+}
+
+/// The requests a proxy is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyScope {
+    All,
+    Http,
+    Https,
+}
+
+static NETWORK: RwLock<Option<Network>> = RwLock::new(None);
+
+/// The client every request goes through, so connections and TLS sessions are reused
+static SHARED: RwLock<Option<Client>> = RwLock::new(None);
+
+/// Redirects followed within one origin before giving up
+const MAX_REDIRECTS: usize = 10;
+/// Longest wait for a connection to be set up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest wait for the next bytes of a response; large downloads may take
+/// as long as they need as long as data keeps coming
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Validates the proxy and reads the certificate files; clients built after this use them
+pub fn configure(args: &NetworkArgs) -> Result<()> {
+    let mut network = Network::default();
+    let auth = match &args.proxy_auth {
+        Some(auth) => Some(
+            auth.expose()
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("--proxy-auth must be user:password"))?,
+        ),
+        None => None,
+    };
+    for (scope, url) in proxy_urls(args, |name| std::env::var(name).ok())? {
+        let proxy = match scope {
+            ProxyScope::All => Proxy::all(&url),
+            ProxyScope::Http => Proxy::http(&url),
+            ProxyScope::Https => Proxy::https(&url),
+        }
+        .with_context(|| format!("invalid proxy url: {url}"))?;
+        let proxy = match auth {
+            Some((user, password)) => proxy.basic_auth(user, password),
+            None => proxy,
+        };
+        let no_proxy = match args.no_proxy.as_deref() {
+            Some(hosts) => NoProxy::from_string(hosts),
+            None => NoProxy::from_env(),
+        };
+        network.proxies.push(proxy.no_proxy(no_proxy));
+    }
+    for path in &args.ca_certs {
+        network.roots.extend(load_roots(path)?);
+    }
+    if let Some(cert) = &args.client_cert {
+        network.identity = Some(load_identity(cert, args.client_key.as_deref())?);
+    }
+    *NETWORK.write().unwrap() = Some(network);
+    *SHARED.write().unwrap() = None;
+    Ok(())
+}
+
+/// The proxies to set up: `--proxy` for everything, or the ones from the
+/// environment when `--proxy-auth` or `--no-proxy` have to be applied to them.
+/// Without any of those flags reqwest reads the environment by itself.
+fn proxy_urls(
+    args: &NetworkArgs,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(ProxyScope, String)>> {
+    if let Some(url) = &args.proxy {
+        return Ok(vec![(ProxyScope::All, url.clone())]);
+    }
+    if args.proxy_auth.is_none() && args.no_proxy.is_none() {
+        return Ok(Vec::new());
+    }
+    let var = |name: &str| {
+        env(name)
+            .or_else(|| env(&name.to_ascii_lowercase()))
+            .filter(|v| !v.is_empty())
+    };
+    // like reqwest, the proxy for a scheme wins over ALL_PROXY
+    let urls: Vec<_> = [
+        (ProxyScope::Https, "HTTPS_PROXY"),
+        (ProxyScope::Http, "HTTP_PROXY"),
+        (ProxyScope::All, "ALL_PROXY"),
+    ]
+    .into_iter()
+    .filter_map(|(scope, name)| Some((scope, var(name)?)))
+    .collect();
+    if urls.is_empty() {
+        anyhow::bail!(
+            "--proxy-auth and --no-proxy need --proxy or a proxy in HTTPS_PROXY, HTTP_PROXY or ALL_PROXY"
+        );
+    }
+    Ok(urls)
+}
+
+fn load_roots(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = read(path)?;
+    let certs = Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("invalid certificate in {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_identity(cert: &Path, key: Option<&Path>) -> Result<Identity> {
+    let mut pem = read(cert)?;
+    if let Some(key) = key {
+        pem.push(b'\n');
+        pem.extend(read(key)?);
+    }
+    Identity::from_pem(&pem).with_context(|| {
+        format!(
+            "invalid client certificate or key in {} (PEM with a certificate and a private key expected)",
+            cert.display()
+        )
+    })
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// The shared client, built on first use with the settings from [`configure`].
+/// Clones share one connection pool.
+///
+/// Redirects are only followed within the same origin; a redirect to another
+/// host is returned as is, for the caller to follow with that host's credentials.
+pub fn http_client() -> Result<Client> {
+    if let Some(client) = SHARED.read().unwrap().as_ref() {
+        return Ok(client.clone());
+    }
+    let mut shared = SHARED.write().unwrap();
+    if let Some(client) = shared.as_ref() {
+        return Ok(client.clone());
+    }
+    let client = client_builder()?
+        .redirect(Policy::custom(|attempt| {
+            let same_origin = attempt
+                .previous()
+                .last()
+                .is_some_and(|prev| prev.origin() == attempt.url().origin());
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if same_origin {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()?;
+    *shared = Some(client.clone());
+    Ok(client)
+}
+
+fn client_builder() -> Result<ClientBuilder> {
+    let mut builder = ClientBuilder::new()
         .user_agent("packwiz-installer-rust/0.1")
         .gzip(true)
         .brotli(true)
         .deflate(true)
         .pool_max_idle_per_host(8)
-        .timeout(Duration::from_secs(30))
-        .build()?;
-    Ok(client)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT);
+    if let Some(network) = NETWORK.read().unwrap().clone() {
+        for proxy in network.proxies {
+            builder = builder.proxy(proxy);
+        }
+        for root in network.roots {
+            builder = builder.add_root_certificate(root);
+        }
+        if let Some(identity) = network.identity {
+            // PEM identities are only supported by the rustls backend
+            builder = builder.use_rustls_tls().identity(identity);
+        }
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::secret::Secret;
+    use crate::request::test_server::{Response, TestServer};
+    use crate::task::test_pack::TempDir;
+    use std::collections::HashMap;
+
+    fn proxies(args: &NetworkArgs, env: &[(&str, &str)]) -> Result<Vec<(ProxyScope, String)>> {
+        let env: HashMap<&str, &str> = env.iter().copied().collect();
+        proxy_urls(args, |name| env.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn selects_proxies() {
+        let env = [
+            ("https_proxy", "http://secure:3128"),
+            ("HTTP_PROXY", "http://plain:3128"),
+            ("ALL_PROXY", ""),
+        ];
+        let explicit = NetworkArgs {
+            proxy: Some("socks5://local:1080".into()),
+            proxy_auth: Some(Secret::new("user:pass")),
+            ..Default::default()
+        };
+        assert_eq!(
+            proxies(&explicit, &env).unwrap(),
+            [(ProxyScope::All, "socks5://local:1080".into())]
+        );
+        // reqwest handles the environment itself when nothing has to be added
+        assert!(proxies(&NetworkArgs::default(), &env).unwrap().is_empty());
+        let auth = NetworkArgs {
+            proxy_auth: Some(Secret::new("user:pass")),
+            ..Default::default()
+        };
+        assert_eq!(
+            proxies(&auth, &env).unwrap(),
+            [
+                (ProxyScope::Https, "http://secure:3128".into()),
+                (ProxyScope::Http, "http://plain:3128".into()),
+            ]
+        );
+        let no_proxy = NetworkArgs {
+            no_proxy: Some("example.com".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            proxies(&no_proxy, &[("all_proxy", "http://any:3128")]).unwrap(),
+            [(ProxyScope::All, "http://any:3128".into())]
+        );
+        let err = proxies(&no_proxy, &[]).unwrap_err();
+        assert!(err.to_string().contains("need --proxy"), "{err}");
+    }
+
+    #[test]
+    fn reports_unusable_certificates() {
+        let dir = TempDir::new("client-certs");
+        std::fs::write(dir.join("empty.pem"), "").unwrap();
+        std::fs::write(
+            dir.join("junk.pem"),
+            "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n",
+        )
+        .unwrap();
+        let missing = dir.join("missing.pem");
+
+        let err = load_identity(&missing, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("failed to read {}", missing.display())
+        );
+        let err = load_identity(&dir.join("empty.pem"), Some(&missing)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("failed to read {}", missing.display())
+        );
+        let err = load_identity(&dir.join("empty.pem"), None).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid client certificate or key in"),
+            "{err}"
+        );
+
+        let err = load_roots(&dir.join("empty.pem")).unwrap_err();
+        assert!(
+            err.to_string().starts_with("no certificates found in"),
+            "{err}"
+        );
+        let err = load_roots(&dir.join("junk.pem")).unwrap_err();
+        assert!(
+            err.to_string().starts_with("invalid certificate in"),
+            "{err}"
+        );
+        assert!(load_roots(&missing).is_err());
+    }
+
+    #[tokio::test]
+    async fn reuses_connections() {
+        let server = TestServer::start(|req| {
+            assert_eq!(req.method, "GET");
+            Response::new(200, "ok")
+        });
+        for path in ["/a", "/b", "/c"] {
+            let body = http_client()
+                .unwrap()
+                .get(server.url(path))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            assert_eq!(body, "ok");
+        }
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/a", "/b", "/c"]);
+        assert_eq!(server.connections(), 1);
+    }
 }
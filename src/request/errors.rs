@@ -1,7 +1,34 @@
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum RequestError {
     #[error("invalid url: {0}")]
     InvalidUrl(String),
+    #[error("{service} api rejected the request (403 Forbidden), check the API key: {body}")]
+    Forbidden { service: &'static str, body: String },
+    #[error("{service} api rate limit exceeded (429){}", retry_after_hint(.retry_after))]
+    RateLimited {
+        service: &'static str,
+        retry_after: Option<Duration>,
+    },
+    #[error("{service} api server error {status}: {body}")]
+    Server {
+        service: &'static str,
+        status: u16,
+        body: String,
+    },
+    #[error("{service} api error {status}: {body}")]
+    Status {
+        service: &'static str,
+        status: u16,
+        body: String,
+    },
+}
+
+fn retry_after_hint(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(d) => format!(", retry after {}s", d.as_secs()),
+        None => String::new(),
+    }
 }
@@ -1,2 +1,8 @@
+pub mod archive;
+pub mod auth;
 pub mod client;
 pub mod errors;
+pub mod fetcher;
+pub mod secret;
+#[cfg(test)]
+pub(crate) mod test_server;
@@ -0,0 +1,550 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{StreamExt, stream};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::destination::side::Side;
+use crate::errors::HashMismatch;
+use crate::metadata::index::IndexEntry;
+use crate::metadata::modfile::{DownloadMode, ModToml};
+use crate::task::download::{
+    EntryContext, Metafile, PreparedEntry, fetch_checked, fetch_linked, metafile_dest,
+    prepare_entry,
+};
+use crate::task::manual::{self, ManualDownload, ManualDownloadsMissing};
+use crate::task::report::{FileAction, FileReport, Outcome, PackSummary, Reporter};
+use crate::task::update::{Options, entry_context, load_pack};
+
+/// Folder of the bundle holding the content of metafiles
+const CONTENT_DIR: &str = ".content";
+
+/// Archive formats a bundle can be written as, picked by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn of(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Ok(ArchiveFormat::TarZst)
+        } else {
+            anyhow::bail!(
+                "can't tell the archive format of {}; use a .zip or .tar.zst file name",
+                path.display()
+            )
+        }
+    }
+}
+
+/// Writes files into a zip or tar.zst archive, under a temporary name until
+/// finished. Dropping an unfinished writer removes what was written.
+pub struct ArchiveWriter {
+    inner: Option<Inner>,
+    part: PathBuf,
+    path: PathBuf,
+}
+
+enum Inner {
+    Zip(Box<zip::ZipWriter<File>>),
+    TarZst(tar::Builder<zstd::Encoder<'static, File>>),
+}
+
+impl ArchiveWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::create_as(path, ArchiveFormat::of(path)?)
+    }
+
+    /// [`create`](Self::create) for a file name that doesn't tell the format
+    pub fn create_as(path: &Path, format: ArchiveFormat) -> Result<Self> {
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let file =
+            File::create(&part).with_context(|| format!("failed to create {}", part.display()))?;
+        let inner = match format {
+            ArchiveFormat::Zip => Inner::Zip(Box::new(zip::ZipWriter::new(file))),
+            ArchiveFormat::TarZst => Inner::TarZst(tar::Builder::new(zstd::Encoder::new(file, 0)?)),
+        };
+        Ok(ArchiveWriter {
+            inner: Some(inner),
+            part,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn add(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        match self.inner.as_mut().expect("archive is not finished") {
+            Inner::Zip(zip) => {
+                use zip::write::SimpleFileOptions;
+                // jars are zips already
+                let method = if path.ends_with(".jar") || path.ends_with(".zip") {
+                    zip::CompressionMethod::Stored
+                } else {
+                    zip::CompressionMethod::Deflated
+                };
+                zip.start_file(
+                    path,
+                    SimpleFileOptions::default()
+                        .compression_method(method)
+                        .large_file(data.len() >= u32::MAX as usize),
+                )?;
+                zip.write_all(data)?;
+            }
+            Inner::TarZst(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                tar.append_data(&mut header, path, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the archive and moves it to its final name
+    pub fn finish(mut self) -> Result<()> {
+        match self.inner.take().expect("archive is not finished") {
+            Inner::Zip(zip) => {
+                zip.finish()?;
+            }
+            Inner::TarZst(tar) => {
+                tar.into_inner()?.finish()?;
+            }
+        }
+        std::fs::rename(&self.part, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for ArchiveWriter {
+    fn drop(&mut self) {
+        if self.inner.take().is_some() {
+            let _ = std::fs::remove_file(&self.part);
+        }
+    }
+}
+
+/// An index entry's files in the bundle
+struct Bundled {
+    /// Index path of the entry
+    file: String,
+    /// Path in the bundle and contents
+    files: Vec<(String, Bytes)>,
+    /// New hash of a rewritten metafile, in the index entry's hash format
+    hash: Option<String>,
+    manual: Option<ManualDownload>,
+}
+
+/// Fetches a pack and every file it references, for every side and with every
+/// optional mod, into one archive. Metafiles are rewritten to point at their
+/// content inside the archive, so the unpacked bundle installs without network
+/// access (`packwiz-installer path/to/bundle/pack.toml`).
+pub async fn run_bundle(opts: Options, archive: &Path) -> Result<()> {
+    let report = &opts.report;
+    ArchiveFormat::of(archive)?;
+    let started = Instant::now();
+    let loaded = load_pack(&opts.pack_uri).await?;
+    report.pack(PackSummary::new(&loaded));
+    report.phase("load_pack", started);
+
+    let started = Instant::now();
+    let mut ctx = entry_context(&opts, &loaded)?;
+    ctx.side = Side::Both;
+    let prepared = stream::iter(
+        loaded
+            .index
+            .files
+            .iter()
+            .cloned()
+            .map(|e| prepare_entry(e, &ctx)),
+    )
+    .buffered(opts.concurrency)
+    .collect::<Vec<_>>()
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>>>()?;
+    ctx.option_values = prepared
+        .iter()
+        .filter(|p| p.metafile.is_some())
+        .map(|p| (p.entry.file.clone(), true))
+        .collect();
+    let cf_ids: Vec<(i64, i64)> = prepared
+        .iter()
+        .filter_map(|p| p.curseforge_ids(&ctx))
+        .collect();
+    if !cf_ids.is_empty() {
+        ctx.curseforge_urls = crate::cf_get_download_urls(&opts.curseforge, &cf_ids)
+            .await
+            .with_context(|| "failed to resolve CurseForge downloads")?;
+    }
+    report.phase("resolve", started);
+
+    let started = Instant::now();
+    let index_path = loaded
+        .pack
+        .index
+        .as_ref()
+        .map(|i| i.file.clone())
+        .unwrap_or_else(|| "index.toml".into());
+    let index_dir = parent(&index_path);
+    let downloads_dir = opts
+        .downloads_dir
+        .clone()
+        .or_else(manual::default_downloads_dir);
+    let mut writer = ArchiveWriter::create(archive)?;
+    let mut hashes = HashMap::new();
+    let mut pending = Vec::new();
+    let mut results = stream::iter(
+        prepared
+            .into_iter()
+            .map(|p| bundle_entry(p, &ctx, index_dir, downloads_dir.as_deref())),
+    )
+    .buffered(opts.concurrency);
+    while let Some(bundled) = results.next().await.transpose()? {
+        for (path, data) in &bundled.files {
+            writer.add(path, data)?;
+        }
+        // the content, for metafiles
+        if let Some((path, _)) = bundled.files.first() {
+            let mut file = FileReport::new(&bundled.file, FileAction::Download, Outcome::Ok);
+            file.location = (*path != bundled.file).then(|| path.clone());
+            report.file(file);
+        }
+        if let Some(hash) = bundled.hash {
+            hashes.insert(bundled.file, hash);
+        }
+        pending.extend(bundled.manual);
+    }
+    if !pending.is_empty() {
+        return Err(missing_downloads(
+            report,
+            pending,
+            downloads_dir.as_deref(),
+            "bundle",
+        ));
+    }
+
+    let index = rewrite_index(&loaded.index_bytes, &hashes)?;
+    writer.add(&index_path, index.as_bytes())?;
+    let pack = rewrite_pack(
+        &loaded.pack_bytes,
+        &crate::hash_hex(&loaded.index_hash_format, index.as_bytes())?,
+    )?;
+    writer.add("pack.toml", pack.as_bytes())?;
+    writer.finish()?;
+    report.phase("download", started);
+    tracing::info!("wrote {}", archive.display());
+    Ok(())
+}
+
+async fn bundle_entry(
+    prepared: PreparedEntry,
+    ctx: &EntryContext,
+    index_dir: &str,
+    downloads_dir: Option<&Path>,
+) -> Result<Bundled> {
+    let PreparedEntry { entry, metafile } = prepared;
+    let path = join(index_dir, &entry.file);
+    let Some(Metafile {
+        uri,
+        mod_toml,
+        bytes,
+    }) = metafile
+    else {
+        let file_uri = crate::join_uri(&ctx.index_uri, &entry.file)?;
+        let data = crate::fetch_bytes_retry(&file_uri, 3)
+            .await
+            .with_context(|| format!("failed to download {}", entry.file))?;
+        let format = entry
+            .hash_format
+            .as_deref()
+            .unwrap_or(&ctx.index_hash_format_default);
+        let got = crate::hash_hex(format, &data)?;
+        if !got.eq_ignore_ascii_case(&entry.hash) {
+            return Err(HashMismatch {
+                subject: format!("file {}", entry.file),
+                got,
+                expected: entry.hash.clone(),
+                format: format.to_string(),
+            }
+            .into());
+        }
+        return Ok(Bundled {
+            file: entry.file,
+            files: vec![(path, data)],
+            hash: None,
+            manual: None,
+        });
+    };
+
+    let content = match metafile_content(&entry, &uri, &mod_toml, ctx, downloads_dir).await? {
+        Content::Fetched(data) => data,
+        Content::Manual(m) => {
+            return Ok(Bundled {
+                file: entry.file,
+                files: Vec::new(),
+                hash: None,
+                manual: Some(m),
+            });
+        }
+    };
+
+    let stem = path.strip_suffix(".pw.toml").unwrap_or(&path);
+    let content_path = format!("{CONTENT_DIR}/{stem}/{}", safe_name(&mod_toml.filename));
+    let up = "../".repeat(parent(&path).split('/').filter(|s| !s.is_empty()).count());
+    let metafile = rewrite_metafile(&bytes, &format!("{up}{content_path}"))?;
+    let format = entry
+        .hash_format
+        .as_deref()
+        .unwrap_or(&ctx.index_hash_format_default);
+    let hash = crate::hash_hex(format, metafile.as_bytes())?;
+    Ok(Bundled {
+        file: entry.file,
+        files: vec![
+            (content_path, content),
+            (path, Bytes::from(metafile.into_bytes())),
+        ],
+        hash: Some(hash),
+        manual: None,
+    })
+}
+
+/// A metafile's content, or the manual download it still needs
+pub(crate) enum Content {
+    Fetched(Bytes),
+    Manual(ManualDownload),
+}
+
+/// Downloads a metafile's content, taking files CurseForge won't serve from `downloads_dir`
+pub(crate) async fn metafile_content(
+    entry: &IndexEntry,
+    mod_uri: &str,
+    mod_toml: &ModToml,
+    ctx: &EntryContext,
+    downloads_dir: Option<&Path>,
+) -> Result<Content> {
+    let data = match mod_toml.download.mode {
+        DownloadMode::Url => fetch_linked(mod_uri, mod_toml, ctx).await?,
+        DownloadMode::Curseforge => {
+            let cf = mod_toml
+                .update
+                .curseforge
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("curseforge update section missing"))?;
+            match ctx.curseforge_urls.get(&cf.file_id) {
+                Some(Ok(url)) => fetch_checked(url, mod_toml).await?,
+                Some(Err(url)) => {
+                    let m = ManualDownload {
+                        name: mod_toml.name.clone(),
+                        filename: mod_toml.filename.clone(),
+                        url: url.clone(),
+                        dest: metafile_dest(entry, mod_toml),
+                        hash_format: mod_toml.download.hash_format.clone(),
+                        hash: mod_toml.download.hash.clone(),
+                    };
+                    match downloads_dir.and_then(|d| manual::find_download(d, &m)) {
+                        Some(found) => Bytes::from(std::fs::read(&found)?),
+                        None => return Ok(Content::Manual(m)),
+                    }
+                }
+                None => anyhow::bail!("curseforge file {} was not resolved", cf.file_id),
+            }
+        }
+    };
+    Ok(Content::Fetched(data))
+}
+
+/// Lists the files that have to be downloaded by hand before `command` can write its archive
+pub(crate) fn missing_downloads(
+    report: &Reporter,
+    pending: Vec<ManualDownload>,
+    downloads_dir: Option<&Path>,
+    command: &str,
+) -> anyhow::Error {
+    for m in &pending {
+        report.manual_download(m);
+    }
+    manual::print_list(&pending);
+    match downloads_dir {
+        Some(dir) => eprintln!("Save them to {} and run {command} again", dir.display()),
+        None => eprintln!("Save them to a folder and pass it with --downloads-dir"),
+    }
+    ManualDownloadsMissing(pending).into()
+}
+
+/// Points a metafile's download at `url` (relative to the metafile)
+fn rewrite_metafile(bytes: &[u8], url: &str) -> Result<String> {
+    let mut doc: toml::Table = toml::from_str(std::str::from_utf8(bytes)?)?;
+    let download = doc
+        .get_mut("download")
+        .and_then(|d| d.as_table_mut())
+        .ok_or_else(|| anyhow::anyhow!("metafile has no [download] table"))?;
+    download.insert("url".into(), url.into());
+    download.remove("mode");
+    download.remove("mirrors");
+    Ok(toml::to_string(&doc)?)
+}
+
+/// Replaces the hashes of rewritten metafiles in index.toml
+fn rewrite_index(bytes: &[u8], hashes: &HashMap<String, String>) -> Result<String> {
+    let mut doc: toml::Table = toml::from_str(std::str::from_utf8(bytes)?)?;
+    if let Some(files) = doc.get_mut("files").and_then(|f| f.as_array_mut()) {
+        for file in files.iter_mut().filter_map(|f| f.as_table_mut()) {
+            let hash = file
+                .get("file")
+                .and_then(|f| f.as_str())
+                .and_then(|f| hashes.get(f));
+            if let Some(hash) = hash {
+                file.insert("hash".into(), hash.clone().into());
+            }
+        }
+    }
+    Ok(toml::to_string(&doc)?)
+}
+
+/// Sets the index hash in pack.toml
+fn rewrite_pack(bytes: &[u8], index_hash: &str) -> Result<String> {
+    let mut doc: toml::Table = toml::from_str(std::str::from_utf8(bytes)?)?;
+    let index = doc
+        .get_mut("index")
+        .and_then(|i| i.as_table_mut())
+        .ok_or_else(|| anyhow::anyhow!("pack.toml is missing [index]"))?;
+    index.insert("hash".into(), index_hash.into());
+    Ok(toml::to_string(&doc)?)
+}
+
+/// The folder part of a `/`-separated path, `""` for top-level files
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn join(dir: &str, path: &str) -> String {
+    if dir.is_empty() {
+        path.to_string()
+    } else {
+        format!("{dir}/{path}")
+    }
+}
+
+/// A file name that can be used as is in a relative URL
+fn safe_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._+-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::test_pack::{TempDir, TestPack, mod_toml, options};
+    use crate::task::update::run_update;
+    use std::collections::BTreeMap;
+
+    /// Every file under `dir` but the manifest and its pack cache, by relative path
+    fn installed(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+        fn walk(root: &Path, dir: &Path, out: &mut BTreeMap<String, Vec<u8>>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(root, &path, out);
+                } else {
+                    let rel = path
+                        .strip_prefix(root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/");
+                    out.insert(rel, std::fs::read(&path).unwrap());
+                }
+            }
+        }
+        let mut out = BTreeMap::new();
+        walk(dir, dir, &mut out);
+        out.remove("packwiz.json");
+        out.remove("packwiz.http.json");
+        out
+    }
+
+    /// The manifest's files with where they were installed and whether they're enabled
+    fn cached_files(dir: &Path) -> Vec<(String, serde_json::Value, serde_json::Value)> {
+        let text = std::fs::read_to_string(dir.join("packwiz.json")).unwrap();
+        let manifest: serde_json::Value = serde_json::from_str(&text).unwrap();
+        manifest["cachedFiles"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(key, v)| {
+                (
+                    key.clone(),
+                    v["cachedLocation"].clone(),
+                    v["optionValue"].clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn installs_from_a_bundle_like_from_the_pack() {
+        let dir = TempDir::new("bundle-round-trip");
+        let pack = TestPack::new()
+            .file("config/a.txt", "a")
+            .preserved("config/keys.txt", "jump=space\n")
+            .jar("a", "")
+            .jar("opt", "\n[option]\noptional = true\ndefault = false\n")
+            .metafile(
+                "mods/server.pw.toml",
+                &mod_toml("server", "url = \"../jars/server.jar\"", "")
+                    .replace("side = \"both\"", "side = \"server\""),
+            )
+            .extra("jars/server.jar", "server")
+            .write(&dir.join("src"));
+
+        let direct = dir.join("direct");
+        run_update(options(&pack, &direct)).await.unwrap();
+
+        let archives = ["bundle.zip", "bundle.tar.zst"];
+        for name in archives {
+            run_bundle(options(&pack, &dir.join("bundling")), &dir.join(name))
+                .await
+                .unwrap();
+        }
+        // the archive is all the install needs
+        std::fs::remove_dir_all(dir.join("src")).unwrap();
+
+        for name in archives {
+            let target = dir.join(&format!("from-{name}"));
+            run_update(options(dir.join(name).to_str().unwrap(), &target))
+                .await
+                .unwrap();
+
+            let files = installed(&target.join("pack"));
+            assert_eq!(files, installed(&direct.join("pack")), "{name}");
+            assert_eq!(
+                files.keys().map(String::as_str).collect::<Vec<_>>(),
+                ["config/a.txt", "config/keys.txt", "mods/a.jar"]
+            );
+            assert_eq!(
+                cached_files(&target.join("pack")),
+                cached_files(&direct.join("pack")),
+                "{name}"
+            );
+        }
+    }
+}
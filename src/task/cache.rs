@@ -1,11 +1,20 @@
 use serde_json::Value;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
 
+use crate::task::pack_cache::PackCache;
+use crate::task::report::{FileAction, FileReport, Outcome, Reporter};
+
 #[derive(Debug, Default, Clone)]
 pub struct PreviousManifest {
     pub cached_paths: BTreeSet<String>,
     pub side: Option<crate::destination::side::Side>,
+    /// sha256 of the pack.toml that was installed
+    pub pack_hash: Option<String>,
+    /// `optionValue` of every optional entry, keyed by metafile path
+    pub option_values: BTreeMap<String, bool>,
+    /// `cachedLocation` of every entry that has one
+    pub locations: BTreeMap<String, String>,
 }
 
 pub fn load_previous(manifest_path: &Path) -> PreviousManifest {
@@ -17,21 +26,121 @@ pub fn load_previous(manifest_path: &Path) -> PreviousManifest {
         out.side = val
             .get("cachedSide")
             .and_then(|v| serde_json::from_value(v.clone()).ok());
+        out.pack_hash = val
+            .get("packFileHash")
+            .filter(|h| h.get("type").and_then(|t| t.as_str()) == Some("sha256"))
+            .and_then(|h| h.get("value"))
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
         if let Some(obj) = val.get("cachedFiles").and_then(|v| v.as_object()) {
-            for k in obj.keys() {
+            for (k, v) in obj {
                 out.cached_paths.insert(k.clone());
+                if let Some(loc) = v.get("cachedLocation").and_then(|l| l.as_str()) {
+                    out.locations.insert(k.clone(), loc.to_string());
+                }
+                let optional = v.get("isOptional").and_then(|o| o.as_bool()) == Some(true);
+                if optional && let Some(choice) = v.get("optionValue").and_then(|o| o.as_bool()) {
+                    out.option_values.insert(k.clone(), choice);
+                }
             }
         }
     }
     out
 }
 
+/// Deletes files the previous install had but the pack no longer lists.
+/// Returns the (manifest key, location) of every file actually removed.
 pub fn remove_unreferenced(
     previous: &PreviousManifest,
     new_paths: &BTreeSet<String>,
     pack_folder: &Path,
-) {
-    for removed in previous.cached_paths.difference(new_paths) {
-        let _ = std::fs::remove_file(pack_folder.join(removed));
+) -> Vec<(String, String)> {
+    let mut removed = Vec::new();
+    for key in previous.cached_paths.difference(new_paths) {
+        let location = previous.locations.get(key).unwrap_or(key);
+        if std::fs::remove_file(pack_folder.join(location)).is_ok() {
+            removed.push((key.clone(), location.clone()));
+        }
+    }
+    removed
+}
+
+/// Removes every file the manifest tracks, then the manifest and its pack cache
+pub fn clean(pack_folder: &Path, meta_file: &str, report: &Reporter) -> anyhow::Result<()> {
+    let manifest_path = pack_folder.join(meta_file);
+    if !manifest_path.exists() {
+        anyhow::bail!(
+            "nothing to clean: {} does not exist",
+            manifest_path.display()
+        );
+    }
+    let previous = load_previous(&manifest_path);
+    let mut removed = 0usize;
+    for (key, location) in &previous.locations {
+        let path = pack_folder.join(location);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+            let mut file = FileReport::new(key, FileAction::Remove, Outcome::Ok);
+            file.location = Some(location.clone());
+            report.file(file);
+        }
+    }
+    std::fs::remove_file(&manifest_path)?;
+    let pack_cache = PackCache::path(&manifest_path);
+    if pack_cache.exists() {
+        std::fs::remove_file(&pack_cache)?;
+    }
+    tracing::info!("removed {} files and {}", removed, manifest_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::OutputFormat;
+    use crate::task::test_pack::{TempDir, TestPack, options};
+    use crate::task::update::run_update;
+
+    #[tokio::test]
+    async fn clean_removes_installed_files_and_the_pack_cache() {
+        let dir = TempDir::new("clean");
+        let pack = TestPack::new()
+            .file("config/a.txt", "a")
+            .jar("a", "")
+            .write(&dir.join("src"));
+        run_update(options(&pack, dir.path())).await.unwrap();
+        let folder = dir.join("pack");
+        for path in [
+            "config/a.txt",
+            "mods/a.jar",
+            "packwiz.json",
+            "packwiz.http.json",
+        ] {
+            assert!(folder.join(path).exists(), "{path}");
+        }
+
+        clean(
+            &folder,
+            "packwiz.json",
+            &Reporter::new(OutputFormat::Text, "clean"),
+        )
+        .unwrap();
+        for path in [
+            "config/a.txt",
+            "mods/a.jar",
+            "packwiz.json",
+            "packwiz.http.json",
+        ] {
+            assert!(!folder.join(path).exists(), "{path}");
+        }
+        assert!(
+            clean(
+                &folder,
+                "packwiz.json",
+                &Reporter::new(OutputFormat::Text, "clean")
+            )
+            .is_err()
+        );
     }
 }
@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
-use reqwest::Client;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 
+use crate::destination::side::Side;
+use crate::errors::{HashMismatch, PolicyViolation};
 use crate::metadata::index::IndexEntry;
 use crate::metadata::modfile::{DownloadMode, ModToml};
+use crate::task::manual::ManualDownload;
+use crate::task::report::FileAction;
 
 #[derive(Debug)]
 pub struct EntryContext {
@@ -11,71 +16,279 @@ pub struct EntryContext {
     pub index_uri: String,
     pub index_hash_format_default: String,
     pub side: crate::destination::side::Side,
-    pub optional_mode: crate::cli::OptionalMode,
-    pub http: Client,
+    pub modrinth_api_base: String,
+    /// CurseForge download URLs resolved up front, keyed by file ID
+    pub curseforge_urls: HashMap<i64, std::result::Result<String, String>>,
+    /// Whether each optional mod is enabled, keyed by metafile path
+    pub option_values: BTreeMap<String, bool>,
+    /// Where the metafiles that will be installed go, so a metafile for the
+    /// other side with the same destination doesn't delete their file
+    pub installed_dests: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub struct PreparedEntry {
+    pub entry: IndexEntry,
+    pub metafile: Option<Metafile>,
+}
+
+#[derive(Debug)]
+pub struct Metafile {
+    pub uri: String,
+    pub mod_toml: ModToml,
+    /// The metafile as fetched
+    pub bytes: Bytes,
+}
+
+impl PreparedEntry {
+    /// CurseForge (project ID, file ID) this entry needs resolved, if it will be installed
+    pub fn curseforge_ids(&self, ctx: &EntryContext) -> Option<(i64, i64)> {
+        let meta = self.metafile.as_ref()?;
+        if !matches!(meta.mod_toml.download.mode, DownloadMode::Curseforge) {
+            return None;
+        }
+        let (include_side, include_opt) = inclusion(ctx, &self.entry.file, &meta.mod_toml);
+        if !(include_side && include_opt) {
+            return None;
+        }
+        let cf = meta.mod_toml.update.curseforge.as_ref()?;
+        Some((cf.project_id, cf.file_id))
+    }
 }
 
 #[derive(Debug)]
 pub struct EntryResult {
     pub path: String,
     pub value: serde_json::Value,
+    /// Set when the file could not be downloaded automatically
+    pub manual: Option<ManualDownload>,
+    pub action: FileAction,
+    /// Why a file was skipped
+    pub reason: Option<&'static str>,
+}
+
+/// Fetches and parses the metafile for an index entry, if it has one
+pub async fn prepare_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<PreparedEntry> {
+    if !entry.metafile {
+        check_dest(manifest_key(&entry))?;
+        return Ok(PreparedEntry {
+            entry,
+            metafile: None,
+        });
+    }
+    let mod_uri = crate::join_uri(&ctx.index_uri, &entry.file)?;
+    let mod_bytes = crate::fetch_bytes_retry(&mod_uri, 3)
+        .await
+        .with_context(|| format!("failed to fetch metafile: {0}", entry.file))?;
+    let mod_toml: ModToml = toml::from_str(std::str::from_utf8(&mod_bytes)?)
+        .with_context(|| "failed to parse mod metadata")?;
+    check_dest(&metafile_dest(&entry, &mod_toml))?;
+    Ok(PreparedEntry {
+        entry,
+        metafile: Some(Metafile {
+            uri: mod_uri,
+            mod_toml,
+            bytes: mod_bytes,
+        }),
+    })
+}
+
+/// Refuses to install over the pack folder's installer config, which would
+/// otherwise let the pack change the settings of the next run
+fn check_dest(dest: &str) -> Result<()> {
+    let name = dest.trim_start_matches("./");
+    if name.eq_ignore_ascii_case(crate::config::PACK_CONFIG_FILE) {
+        return Err(PolicyViolation(format!(
+            "the pack may not install {}",
+            crate::config::PACK_CONFIG_FILE
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Where a metafile's content is installed, relative to the pack folder
+pub(crate) fn metafile_dest(entry: &IndexEntry, mod_toml: &ModToml) -> String {
+    let dest = entry
+        .alias
+        .clone()
+        .unwrap_or_else(|| mod_toml.filename.clone());
+    if dest.contains('/') {
+        dest
+    } else {
+        format!("mods/{dest}")
+    }
+}
+
+/// Key of an index entry in the manifest: the metafile path for mods, and where
+/// the file is installed for everything else
+pub(crate) fn manifest_key(entry: &IndexEntry) -> &str {
+    match &entry.alias {
+        Some(alias) if !entry.metafile => alias,
+        _ => &entry.file,
+    }
+}
+
+/// Whether a mod for `mod_side` is installed when installing `side`
+pub(crate) fn on_side(side: Side, mod_side: Side) -> bool {
+    !matches!(
+        (side, mod_side),
+        (Side::Client, Side::Server) | (Side::Server, Side::Client)
+    )
+}
+
+/// Whether a mod is included for the target side and for the optional mod selection
+pub(crate) fn inclusion(ctx: &EntryContext, path: &str, mod_toml: &ModToml) -> (bool, bool) {
+    let include_side = on_side(ctx.side, mod_toml.side);
+    let include_opt = !mod_toml.option.optional
+        || ctx
+            .option_values
+            .get(path)
+            .copied()
+            .unwrap_or(mod_toml.option.default_value);
+    (include_side, include_opt)
+}
+
+/// Downloads the content of a `url`-mode metafile, asking the Modrinth API for
+/// another URL if that fails, and checks it against the metafile's hash
+pub(crate) async fn fetch_linked(
+    mod_uri: &str,
+    mod_toml: &ModToml,
+    ctx: &EntryContext,
+) -> Result<Bytes> {
+    let mut fetched = match mod_toml.download.url.as_deref() {
+        Some(url) => fetch_content(&crate::join_uri(mod_uri, url)?).await,
+        None => Err(anyhow::anyhow!("download.url missing")),
+    };
+    for mirror in &mod_toml.download.mirrors {
+        let Err(e) = &fetched else {
+            break;
+        };
+        tracing::warn!(
+            "download for {} failed ({:#}); trying {}",
+            mod_toml.name,
+            e,
+            crate::request::auth::redact_url(mirror)
+        );
+        fetched = fetch_content(&crate::join_uri(mod_uri, mirror)?).await;
+    }
+    let bytes = match (fetched, mod_toml.update.modrinth.as_ref()) {
+        (Ok(bytes), _) => bytes,
+        (Err(e), Some(mr)) => {
+            tracing::warn!(
+                "download for {} failed ({:#}); resolving via Modrinth version {}",
+                mod_toml.name,
+                e,
+                mr.version
+            );
+            let url = crate::mr_get_download_url(
+                &ctx.modrinth_api_base,
+                &mr.version,
+                &mod_toml.filename,
+                &mod_toml.download.hash_format,
+                &mod_toml.download.hash,
+            )
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("modrinth version {} has no files", mr.version))?;
+            fetch_content(&url).await?
+        }
+        (Err(e), None) => return Err(e),
+    };
+    check_linked(mod_toml, &bytes)?;
+    Ok(bytes)
+}
+
+/// Downloads a metafile's content from `url` and checks it against the metafile's hash
+pub(crate) async fn fetch_checked(url: &str, mod_toml: &ModToml) -> Result<Bytes> {
+    let bytes = fetch_content(url).await?;
+    check_linked(mod_toml, &bytes)?;
+    Ok(bytes)
+}
+
+async fn fetch_content(url: &str) -> Result<Bytes> {
+    crate::fetch_bytes_retry(url, 3).await.with_context(|| {
+        format!(
+            "failed to download {}",
+            crate::request::auth::redact_url(url)
+        )
+    })
+}
+
+fn check_linked(mod_toml: &ModToml, bytes: &[u8]) -> Result<()> {
+    let h = crate::hash_hex(&mod_toml.download.hash_format, bytes)?;
+    if h != mod_toml.download.hash {
+        return Err(HashMismatch {
+            subject: format!("mod {}", mod_toml.name),
+            got: h,
+            expected: mod_toml.download.hash.clone(),
+            format: mod_toml.download.hash_format.clone(),
+        }
+        .into());
+    }
+    Ok(())
 }
 
-pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Option<EntryResult>> {
+pub async fn process_entry(
+    prepared: PreparedEntry,
+    ctx: &EntryContext,
+) -> Result<Option<EntryResult>> {
+    let PreparedEntry { entry, metafile } = prepared;
     let file_hash_fmt_owned = entry
         .hash_format
         .clone()
         .unwrap_or(ctx.index_hash_format_default.clone());
-    if entry.metafile {
-        let mod_uri = crate::join_uri(&ctx.index_uri, &entry.file)?;
-        let mod_bytes = crate::fetch_bytes_retry(&mod_uri, 3)
-            .await
-            .with_context(|| format!("failed to fetch metafile: {0}", entry.file))?;
-        let mod_toml: ModToml = toml::from_str(std::str::from_utf8(&mod_bytes)?)
-            .with_context(|| "failed to parse mod metadata")?;
-        let include_side = match (ctx.side, mod_toml.side) {
-            (crate::destination::side::Side::Both, _) => true,
-            (crate::destination::side::Side::Client, crate::destination::side::Side::Server) => {
-                false
-            }
-            (crate::destination::side::Side::Server, crate::destination::side::Side::Client) => {
-                false
-            }
-            _ => true,
-        };
-        let include_opt = match ctx.optional_mode {
-            crate::cli::OptionalMode::Default => {
-                !mod_toml.option.optional || mod_toml.option.default_value
-            }
-            crate::cli::OptionalMode::All => true,
-            crate::cli::OptionalMode::None => !mod_toml.option.optional,
-        };
+    if let Some(Metafile {
+        uri: mod_uri,
+        mod_toml,
+        ..
+    }) = metafile
+    {
+        let dest_rel_val = metafile_dest(&entry, &mod_toml);
+        let (include_side, include_opt) = inclusion(ctx, &entry.file, &mod_toml);
         if !(include_side && include_opt) {
-            let temp = entry
-                .alias
-                .clone()
-                .unwrap_or_else(|| mod_toml.filename.clone());
-            let _ = std::fs::remove_file(ctx.pack_folder.join(&temp));
+            if !ctx.installed_dests.contains(&dest_rel_val) {
+                let _ = std::fs::remove_file(ctx.pack_folder.join(&dest_rel_val));
+            }
 
-            // If excluded only due to side restriction (not optional), create onlyOtherSide entry
-            if !include_side && include_opt {
+            // Remember that an optional mod was turned off so later updates keep it off
+            if !include_opt {
                 let mut file_obj = serde_json::Map::new();
-                file_obj.insert("optionValue".into(), serde_json::Value::Bool(true));
-                file_obj.insert("onlyOtherSide".into(), serde_json::Value::Bool(true));
+                let mut meta_hash = serde_json::Map::new();
+                meta_hash.insert(
+                    "type".into(),
+                    serde_json::Value::String(file_hash_fmt_owned.clone()),
+                );
+                meta_hash.insert(
+                    "value".into(),
+                    serde_json::Value::String(entry.hash.clone()),
+                );
+                file_obj.insert("hash".into(), serde_json::Value::Object(meta_hash));
+                file_obj.insert("isOptional".into(), serde_json::Value::Bool(true));
+                file_obj.insert("optionValue".into(), serde_json::Value::Bool(false));
                 return Ok(Some(EntryResult {
                     path: entry.file.clone(),
                     value: serde_json::Value::Object(file_obj),
+                    manual: None,
+                    action: FileAction::Skip,
+                    reason: Some("optional mod disabled"),
                 }));
             }
 
-            return Ok(None);
-        }
-        let mut dest_rel_val = entry
-            .alias
-            .clone()
-            .unwrap_or_else(|| mod_toml.filename.clone());
-        if !dest_rel_val.contains('/') {
-            dest_rel_val = format!("mods/{dest_rel_val}");
+            // Excluded only due to side restriction, create onlyOtherSide entry; an
+            // optional mod keeps its selection for when the other side is installed
+            let mut file_obj = serde_json::Map::new();
+            if mod_toml.option.optional {
+                file_obj.insert("isOptional".into(), serde_json::Value::Bool(true));
+                file_obj.insert("optionValue".into(), serde_json::Value::Bool(include_opt));
+            }
+            file_obj.insert("onlyOtherSide".into(), serde_json::Value::Bool(true));
+            return Ok(Some(EntryResult {
+                path: entry.file.clone(),
+                value: serde_json::Value::Object(file_obj),
+                manual: None,
+                action: FileAction::Skip,
+                reason: Some("other side only"),
+            }));
         }
         let dest_abs = ctx.pack_folder.join(&dest_rel_val);
         match mod_toml.download.mode {
@@ -87,30 +300,18 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                 {
                     got = Some(h);
                 }
+                let action = if got.is_some() {
+                    FileAction::Keep
+                } else {
+                    FileAction::Download
+                };
                 if got.is_none() {
-                    let url = mod_toml
-                        .download
-                        .url
-                        .ok_or_else(|| anyhow::anyhow!("download.url missing"))?;
-                    let url_abs = crate::join_uri(&mod_uri, &url)?;
-                    let bytes = crate::fetch_bytes_retry(&url_abs, 3)
-                        .await
-                        .with_context(|| format!("failed to download {url_abs}"))?;
-                    let h = crate::hash_hex(&mod_toml.download.hash_format, &bytes)?;
-                    if h != mod_toml.download.hash {
-                        anyhow::bail!(
-                            "mod hash mismatch for {}: got {}, expected {} ({})",
-                            mod_toml.name,
-                            h,
-                            mod_toml.download.hash,
-                            mod_toml.download.hash_format
-                        );
-                    }
+                    let bytes = fetch_linked(&mod_uri, &mod_toml, ctx).await?;
                     if let Some(parent) = dest_abs.parent() {
                         std::fs::create_dir_all(parent).ok();
                     }
                     std::fs::write(&dest_abs, &bytes)?;
-                    got = Some(h);
+                    got = Some(mod_toml.download.hash.clone());
                 }
                 let mut file_obj = serde_json::Map::new();
                 // metafile hash from index
@@ -150,14 +351,25 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                 Ok(Some(EntryResult {
                     path: entry.file.clone(),
                     value: serde_json::Value::Object(file_obj),
+                    manual: None,
+                    action,
+                    reason: None,
                 }))
             }
             DownloadMode::Curseforge => {
                 let cf = mod_toml
                     .update
                     .curseforge
+                    .as_ref()
                     .ok_or_else(|| anyhow::anyhow!("curseforge update section missing"))?;
-                match crate::cf_get_download_url(&ctx.http, cf.project_id, cf.file_id).await? {
+                let resolved = ctx
+                    .curseforge_urls
+                    .get(&cf.file_id)
+                    .cloned()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("curseforge file {} was not resolved", cf.file_id)
+                    })?;
+                match resolved {
                     Ok(url) => {
                         let mut got = None;
                         if dest_abs.exists()
@@ -167,25 +379,18 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                         {
                             got = Some(h);
                         }
+                        let action = if got.is_some() {
+                            FileAction::Keep
+                        } else {
+                            FileAction::Download
+                        };
                         if got.is_none() {
-                            let bytes = crate::fetch_bytes_retry(&url, 3)
-                                .await
-                                .with_context(|| format!("failed to download {url}"))?;
-                            let h = crate::hash_hex(&mod_toml.download.hash_format, &bytes)?;
-                            if h != mod_toml.download.hash {
-                                anyhow::bail!(
-                                    "mod hash mismatch for {}: got {}, expected {} ({})",
-                                    mod_toml.name,
-                                    h,
-                                    mod_toml.download.hash,
-                                    mod_toml.download.hash_format
-                                );
-                            }
+                            let bytes = fetch_checked(&url, &mod_toml).await?;
                             if let Some(parent) = dest_abs.parent() {
                                 std::fs::create_dir_all(parent).ok();
                             }
                             std::fs::write(&dest_abs, &bytes)?;
-                            got = Some(h);
+                            got = Some(mod_toml.download.hash.clone());
                         }
                         let mut file_obj = serde_json::Map::new();
                         let mut meta_hash = serde_json::Map::new();
@@ -222,13 +427,26 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                         Ok(Some(EntryResult {
                             path: entry.file.clone(),
                             value: serde_json::Value::Object(file_obj),
+                            manual: None,
+                            action,
+                            reason: None,
                         }))
                     }
                     Err(manual_url) => {
-                        tracing::warn!(
-                            "CurseForge API excluded file; manual download needed: {}",
-                            manual_url
-                        );
+                        let mut got = None;
+                        if dest_abs.exists()
+                            && let Ok(h) =
+                                crate::hash_file_hex(&mod_toml.download.hash_format, &dest_abs)
+                            && h == mod_toml.download.hash
+                        {
+                            got = Some(h);
+                        }
+                        if got.is_none() {
+                            tracing::warn!(
+                                "CurseForge API excluded file; manual download needed: {}",
+                                manual_url
+                            );
+                        }
                         let mut file_obj = serde_json::Map::new();
                         let mut meta_hash = serde_json::Map::new();
                         let meta_fmt = entry
@@ -242,6 +460,18 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                             serde_json::Value::String(entry.hash.clone()),
                         );
                         file_obj.insert("hash".into(), serde_json::Value::Object(meta_hash));
+                        if let Some(h) = got.clone() {
+                            let mut content_hash = serde_json::Map::new();
+                            content_hash.insert(
+                                "type".into(),
+                                serde_json::Value::String(mod_toml.download.hash_format.clone()),
+                            );
+                            content_hash.insert("value".into(), serde_json::Value::String(h));
+                            file_obj.insert(
+                                "linkedFileHash".into(),
+                                serde_json::Value::Object(content_hash),
+                            );
+                        }
                         if mod_toml.option.optional {
                             file_obj.insert("isOptional".into(), serde_json::Value::Bool(true));
                         }
@@ -250,9 +480,24 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                             serde_json::Value::String(dest_rel_val.clone()),
                         );
                         file_obj.insert("optionValue".into(), serde_json::Value::Bool(true));
+                        let manual = got.is_none().then(|| ManualDownload {
+                            name: mod_toml.name.clone(),
+                            filename: mod_toml.filename.clone(),
+                            url: manual_url,
+                            dest: dest_rel_val.clone(),
+                            hash_format: mod_toml.download.hash_format.clone(),
+                            hash: mod_toml.download.hash.clone(),
+                        });
                         Ok(Some(EntryResult {
                             path: entry.file.clone(),
                             value: serde_json::Value::Object(file_obj),
+                            manual,
+                            action: if got.is_some() {
+                                FileAction::Keep
+                            } else {
+                                FileAction::Manual
+                            },
+                            reason: None,
                         }))
                     }
                 }
@@ -260,7 +505,7 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
         }
     } else {
         let file_uri = crate::join_uri(&ctx.index_uri, &entry.file)?;
-        let dest_rel_val = entry.alias.clone().unwrap_or_else(|| entry.file.clone());
+        let dest_rel_val = manifest_key(&entry).to_string();
         let dest_abs = ctx.pack_folder.join(&dest_rel_val);
         let mut got = None;
         if dest_abs.exists()
@@ -269,6 +514,11 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
         {
             got = Some(h);
         }
+        let action = if got.is_some() || (entry.preserve && dest_abs.exists()) {
+            FileAction::Keep
+        } else {
+            FileAction::Download
+        };
         if entry.preserve && dest_abs.exists() {
             // keep
         } else if got.is_none() {
@@ -277,13 +527,13 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
                 .with_context(|| format!("failed to download {0}", entry.file))?;
             let h = crate::hash_hex(&file_hash_fmt_owned, &bytes)?;
             if h != entry.hash {
-                anyhow::bail!(
-                    "file hash mismatch for {}: got {}, expected {} ({})",
-                    entry.file,
-                    h,
-                    entry.hash,
-                    file_hash_fmt_owned
-                );
+                return Err(HashMismatch {
+                    subject: format!("file {}", entry.file),
+                    got: h,
+                    expected: entry.hash.clone(),
+                    format: file_hash_fmt_owned,
+                }
+                .into());
             }
             if let Some(parent) = dest_abs.parent() {
                 std::fs::create_dir_all(parent).ok();
@@ -306,9 +556,16 @@ pub async fn process_entry(entry: IndexEntry, ctx: &EntryContext) -> Result<Opti
             "cachedLocation".into(),
             serde_json::Value::String(dest_rel_val.clone()),
         );
+        // The user may edit a preserved file, so `verify` only checks that it's there
+        if entry.preserve {
+            file_obj.insert("preserve".into(), serde_json::Value::Bool(true));
+        }
         Ok(Some(EntryResult {
-            path: dest_rel_val,
+            path: dest_rel_val.clone(),
             value: serde_json::Value::Object(file_obj),
+            manual: None,
+            action,
+            reason: None,
         }))
     }
 }
@@ -0,0 +1,442 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{StreamExt, stream};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cli::ExportFormat;
+use crate::destination::side::Side;
+use crate::errors::HashMismatch;
+use crate::metadata::modfile::{DownloadMode, ModToml};
+use crate::metadata::mrpack::{self, MrEnv, MrFile, MrIndex};
+use crate::task::bundle::{
+    ArchiveFormat, ArchiveWriter, Content, metafile_content, missing_downloads,
+};
+use crate::task::cache::PreviousManifest;
+use crate::task::download::{EntryContext, Metafile, PreparedEntry, inclusion, metafile_dest};
+use crate::task::manual::{self, ManualDownload};
+use crate::task::report::{FileAction, FileReport, Outcome, PackSummary};
+use crate::task::update::{Options, entry_context, load_pack, prepare_entries};
+
+/// CurseForge's `manifest.json`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CfManifest {
+    minecraft: CfMinecraft,
+    manifest_type: &'static str,
+    manifest_version: u32,
+    name: String,
+    version: String,
+    author: String,
+    files: Vec<CfFile>,
+    overrides: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CfMinecraft {
+    version: String,
+    mod_loaders: Vec<CfLoader>,
+}
+
+#[derive(Debug, Serialize)]
+struct CfLoader {
+    id: String,
+    primary: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct CfFile {
+    #[serde(rename = "projectID")]
+    project_id: i64,
+    #[serde(rename = "fileID")]
+    file_id: i64,
+    required: bool,
+}
+
+enum Exported {
+    /// Stored in the archive, at this path
+    Override(String, Bytes),
+    Modrinth(MrFile),
+    Curseforge(CfFile),
+    Manual(ManualDownload),
+}
+
+/// Writes the pack as a Modrinth .mrpack or a CurseForge modpack zip, with the
+/// files for `opts.side` and the selected optional mods. Files the format can
+/// reference are listed in its manifest; everything else goes in the overrides.
+pub async fn run_export(opts: Options, archive: &Path, format: Option<ExportFormat>) -> Result<()> {
+    let report = &opts.report;
+    let format = match format {
+        Some(format) => format,
+        None => ExportFormat::of(archive)?,
+    };
+    let started = Instant::now();
+    let loaded = load_pack(&opts.pack_uri).await?;
+    report.pack(PackSummary::new(&loaded));
+    report.phase("load_pack", started);
+
+    let started = Instant::now();
+    let mut ctx = entry_context(&opts, &loaded)?;
+    let prepared =
+        prepare_entries(&opts, &mut ctx, &PreviousManifest::default(), &loaded.index).await?;
+    let prepared: Vec<PreparedEntry> = prepared
+        .into_iter()
+        .filter(|p| match &p.metafile {
+            Some(meta) => inclusion(&ctx, &p.entry.file, &meta.mod_toml) == (true, true),
+            None => true,
+        })
+        .collect();
+    // A CurseForge manifest lists CurseForge mods by ID, so only a .mrpack has to download them
+    if matches!(format, ExportFormat::Modrinth) {
+        let cf_ids: Vec<(i64, i64)> = prepared
+            .iter()
+            .filter_map(|p| p.curseforge_ids(&ctx))
+            .collect();
+        if !cf_ids.is_empty() {
+            ctx.curseforge_urls = crate::cf_get_download_urls(&opts.curseforge, &cf_ids)
+                .await
+                .with_context(|| "failed to resolve CurseForge downloads")?;
+        }
+    }
+    report.phase("resolve", started);
+
+    let started = Instant::now();
+    let downloads_dir = opts
+        .downloads_dir
+        .clone()
+        .or_else(manual::default_downloads_dir);
+    let mut writer = ArchiveWriter::create_as(archive, ArchiveFormat::Zip)?;
+    let mut mr_files = Vec::new();
+    let mut cf_files = Vec::new();
+    let mut pending = Vec::new();
+    let mut results = stream::iter(
+        prepared
+            .into_iter()
+            .map(|p| export_entry(p, &ctx, format, downloads_dir.as_deref())),
+    )
+    .buffered(opts.concurrency);
+    while let Some((file, exported)) = results.next().await.transpose()? {
+        let mut location = None;
+        match exported {
+            Exported::Override(path, data) => {
+                writer.add(&path, &data)?;
+                location = Some(path);
+            }
+            Exported::Modrinth(f) => mr_files.push(f),
+            Exported::Curseforge(f) => cf_files.push(f),
+            Exported::Manual(m) => {
+                pending.push(m);
+                continue;
+            }
+        }
+        let mut report_file = FileReport::new(&file, FileAction::Download, Outcome::Ok);
+        report_file.location = location;
+        report.file(report_file);
+    }
+    if !pending.is_empty() {
+        return Err(missing_downloads(
+            report,
+            pending,
+            downloads_dir.as_deref(),
+            "export",
+        ));
+    }
+
+    let pack = &loaded.pack;
+    let minecraft = pack.versions.minecraft().unwrap_or_default().to_string();
+    match format {
+        ExportFormat::Modrinth => {
+            let mut dependencies = BTreeMap::from([("minecraft".to_string(), minecraft)]);
+            for (loader, version) in pack.versions.loaders() {
+                dependencies.insert(loader.modrinth_key().to_string(), version.to_string());
+            }
+            let index = MrIndex {
+                format_version: 1,
+                game: "minecraft".into(),
+                version_id: pack.version.clone().unwrap_or_default(),
+                name: pack.name.clone().unwrap_or_default(),
+                summary: pack.description.clone(),
+                files: mr_files,
+                dependencies,
+            };
+            writer.add(
+                mrpack::INDEX_FILE,
+                serde_json::to_string_pretty(&index)?.as_bytes(),
+            )?;
+        }
+        ExportFormat::Curseforge => {
+            let primary = pack.versions.loader().map(|(l, _)| l);
+            let manifest = CfManifest {
+                minecraft: CfMinecraft {
+                    version: minecraft,
+                    mod_loaders: pack
+                        .versions
+                        .loaders()
+                        .into_iter()
+                        .map(|(loader, version)| CfLoader {
+                            id: format!("{}-{version}", loader.key()),
+                            primary: Some(loader) == primary,
+                        })
+                        .collect(),
+                },
+                manifest_type: "minecraftModpack",
+                manifest_version: 1,
+                name: pack.name.clone().unwrap_or_default(),
+                version: pack.version.clone().unwrap_or_default(),
+                author: pack.author.clone().unwrap_or_default(),
+                files: cf_files,
+                overrides: "overrides",
+            };
+            writer.add(
+                "manifest.json",
+                serde_json::to_string_pretty(&manifest)?.as_bytes(),
+            )?;
+        }
+    }
+    writer.finish()?;
+    report.phase("download", started);
+    tracing::info!("wrote {}", archive.display());
+    Ok(())
+}
+
+async fn export_entry(
+    prepared: PreparedEntry,
+    ctx: &EntryContext,
+    format: ExportFormat,
+    downloads_dir: Option<&Path>,
+) -> Result<(String, Exported)> {
+    let PreparedEntry { entry, metafile } = prepared;
+    let Some(Metafile { uri, mod_toml, .. }) = metafile else {
+        let file_uri = crate::join_uri(&ctx.index_uri, &entry.file)?;
+        let data = crate::fetch_bytes_retry(&file_uri, 3)
+            .await
+            .with_context(|| format!("failed to download {}", entry.file))?;
+        let hash_format = entry
+            .hash_format
+            .as_deref()
+            .unwrap_or(&ctx.index_hash_format_default);
+        let got = crate::hash_hex(hash_format, &data)?;
+        if !got.eq_ignore_ascii_case(&entry.hash) {
+            return Err(HashMismatch {
+                subject: format!("file {}", entry.file),
+                got,
+                expected: entry.hash.clone(),
+                format: hash_format.to_string(),
+            }
+            .into());
+        }
+        let dest = entry.alias.clone().unwrap_or_else(|| entry.file.clone());
+        return Ok((
+            entry.file,
+            Exported::Override(format!("overrides/{dest}"), data),
+        ));
+    };
+    let dest = metafile_dest(&entry, &mod_toml);
+
+    // CurseForge modpacks reference every file CurseForge hosts instead of shipping it,
+    // whichever way the metafile downloads it
+    if matches!(format, ExportFormat::Curseforge)
+        && let Some(cf) = &mod_toml.update.curseforge
+    {
+        let file = CfFile {
+            project_id: cf.project_id,
+            file_id: cf.file_id,
+            required: true,
+        };
+        return Ok((entry.file, Exported::Curseforge(file)));
+    }
+
+    let data = match metafile_content(&entry, &uri, &mod_toml, ctx, downloads_dir).await? {
+        Content::Fetched(data) => data,
+        Content::Manual(m) => return Ok((entry.file, Exported::Manual(m))),
+    };
+    if matches!(format, ExportFormat::Modrinth)
+        && let Some(url) = modrinth_download(&uri, &mod_toml)
+    {
+        let file = MrFile {
+            path: dest,
+            hashes: BTreeMap::from([
+                ("sha1".into(), crate::hash_hex("sha1", &data)?),
+                ("sha512".into(), crate::hash_hex("sha512", &data)?),
+            ]),
+            env: Some(MrEnv::for_side(mod_toml.side)),
+            downloads: vec![url],
+            file_size: data.len() as u64,
+        };
+        return Ok((entry.file, Exported::Modrinth(file)));
+    }
+    // Keep side-only mods out of the other side's install when the export is for both
+    let folder = match (format, ctx.side, mod_toml.side) {
+        (ExportFormat::Modrinth, Side::Both, Side::Client) => "client-overrides",
+        (ExportFormat::Modrinth, Side::Both, Side::Server) => "server-overrides",
+        _ => "overrides",
+    };
+    Ok((
+        entry.file,
+        Exported::Override(format!("{folder}/{dest}"), data),
+    ))
+}
+
+/// The metafile's download URL, if a .mrpack may reference it; files hosted
+/// anywhere else are put in the overrides
+fn modrinth_download(mod_uri: &str, mod_toml: &ModToml) -> Option<String> {
+    if !matches!(mod_toml.download.mode, DownloadMode::Url) {
+        return None;
+    }
+    let url = crate::join_uri(mod_uri, mod_toml.download.url.as_deref()?).ok()?;
+    mrpack::allowed_download(&url).then_some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::fetcher::{self, Fetcher, HttpFetcher};
+    use crate::task::test_pack::{TempDir, TestPack, mod_toml, options, read_zip};
+    use futures::future::BoxFuture;
+    use reqwest::Url;
+
+    /// Serves `cdn.modrinth.com` from memory and passes every other https URL on
+    struct ModrinthCdn;
+
+    impl Fetcher for ModrinthCdn {
+        fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<Bytes>> {
+            Box::pin(async move {
+                match (url.host_str(), url.path()) {
+                    (Some("cdn.modrinth.com"), "/data/mr.jar") => Ok(Bytes::from("mr")),
+                    (Some("cdn.modrinth.com"), _) => anyhow::bail!("{url} not found"),
+                    _ => HttpFetcher.fetch(url).await,
+                }
+            })
+        }
+    }
+
+    /// A local mod, a client-only mod, a Modrinth-hosted mod, a disabled
+    /// optional mod and a config file
+    fn pack() -> TestPack {
+        TestPack::new()
+            .versions("minecraft = \"1.21.1\"\nfabric = \"0.16.0\"\n")
+            .file("config/a.txt", "a")
+            .jar("local", "")
+            .metafile(
+                "mods/mr.pw.toml",
+                &mod_toml("mr", "url = \"https://cdn.modrinth.com/data/mr.jar\"", ""),
+            )
+            .metafile(
+                "mods/client.pw.toml",
+                &mod_toml("client", "url = \"../jars/client.jar\"", "")
+                    .replace("side = \"both\"", "side = \"client\""),
+            )
+            .extra("jars/client.jar", "client")
+            .jar("off", "\n[option]\noptional = true\ndefault = false\n")
+    }
+
+    #[tokio::test]
+    async fn curseforge_manifest_lists_every_curseforge_mod() {
+        fetcher::register("https", ModrinthCdn);
+        let dir = TempDir::new("export-curseforge");
+        let pack = pack()
+            .jar(
+                "cfurl",
+                "\n[update.curseforge]\nproject-id = 10\nfile-id = 100\n",
+            )
+            .metafile(
+                "mods/cfmode.pw.toml",
+                &mod_toml(
+                    "cfmode",
+                    "mode = \"metadata:curseforge\"",
+                    "\n[update.curseforge]\nproject-id = 20\nfile-id = 200\n",
+                ),
+            )
+            .write(&dir.join("src"));
+        let archive = dir.join("pack.zip");
+        // CurseForge files are only referenced, never downloaded
+        let mut opts = options(&pack, dir.path());
+        opts.curseforge.base_url = "http://127.0.0.1:1".into();
+        opts.side = Side::Both;
+        run_export(opts, &archive, None).await.unwrap();
+
+        let files = read_zip(&archive);
+        let mut names: Vec<&str> = files.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "manifest.json",
+                "overrides/config/a.txt",
+                "overrides/mods/client.jar",
+                "overrides/mods/local.jar",
+                "overrides/mods/mr.jar",
+            ]
+        );
+        assert_eq!(files["overrides/mods/local.jar"], "local");
+        let manifest: serde_json::Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+        assert_eq!(
+            manifest,
+            serde_json::json!({
+                "minecraft": {
+                    "version": "1.21.1",
+                    "modLoaders": [{ "id": "fabric-0.16.0", "primary": true }],
+                },
+                "manifestType": "minecraftModpack",
+                "manifestVersion": 1,
+                "name": "Test",
+                "version": "1.0.0",
+                "author": "",
+                "files": [
+                    { "projectID": 10, "fileID": 100, "required": true },
+                    { "projectID": 20, "fileID": 200, "required": true },
+                ],
+                "overrides": "overrides",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn mrpack_lists_files_on_allowed_hosts() {
+        fetcher::register("https", ModrinthCdn);
+        let dir = TempDir::new("export-modrinth");
+        let pack = pack().write(&dir.join("src"));
+        let archive = dir.join("pack.mrpack");
+        let mut opts = options(&pack, dir.path());
+        opts.side = Side::Both;
+        run_export(opts, &archive, None).await.unwrap();
+
+        let files = read_zip(&archive);
+        let mut names: Vec<&str> = files.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "client-overrides/mods/client.jar",
+                "modrinth.index.json",
+                "overrides/config/a.txt",
+                "overrides/mods/local.jar",
+            ]
+        );
+        let index: serde_json::Value =
+            serde_json::from_slice(&files["modrinth.index.json"]).unwrap();
+        assert_eq!(
+            index,
+            serde_json::json!({
+                "formatVersion": 1,
+                "game": "minecraft",
+                "versionId": "1.0.0",
+                "name": "Test",
+                "files": [{
+                    "path": "mods/mr.jar",
+                    "hashes": {
+                        "sha1": crate::hash_hex("sha1", b"mr").unwrap(),
+                        "sha512": crate::hash_hex("sha512", b"mr").unwrap(),
+                    },
+                    "env": { "client": "required", "server": "required" },
+                    "downloads": ["https://cdn.modrinth.com/data/mr.jar"],
+                    "fileSize": 2,
+                }],
+                "dependencies": { "minecraft": "1.21.1", "fabric-loader": "0.16.0" },
+            })
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+
+use crate::task::report::{PackSummary, Reporter};
+use crate::task::update::load_pack;
+
+/// Prints a summary of a pack without installing anything
+pub async fn run_info(pack_uri: &str, report: &Reporter) -> Result<()> {
+    let loaded = load_pack(pack_uri).await?;
+    let summary = PackSummary::new(&loaded);
+    report.pack(summary.clone());
+    if !report.is_text() {
+        return Ok(());
+    }
+
+    println!(
+        "name:         {}",
+        summary.name.as_deref().unwrap_or("(unnamed)")
+    );
+    if let Some(version) = &summary.version {
+        println!("version:      {version}");
+    }
+    println!("pack-format:  {}", summary.pack_format);
+    println!(
+        "minecraft:    {}",
+        summary.minecraft.as_deref().unwrap_or("(none)")
+    );
+    for (loader, version) in &summary.loaders {
+        println!("{:<13} {version}", format!("{loader}:"));
+    }
+    for (key, value) in &loaded.pack.versions.other {
+        println!("{:<13} {value}", format!("{key}:"));
+    }
+    println!(
+        "files:        {} ({} mods, {} other files)",
+        summary.file_count,
+        summary.mod_count,
+        summary.file_count - summary.mod_count
+    );
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::io::Write as _;
+use std::path::Path;
+
+use crate::metadata::pack::{Loader, PackVersions};
+
+const MINECRAFT_UID: &str = "net.minecraft";
+
+/// Makes the instance's `mmc-pack.json` use the Minecraft and loader versions
+/// from pack.toml: versions are updated, missing loaders added and loaders the
+/// pack doesn't use removed. Returns whether the file changed, in which case
+/// the instance has to be restarted. Does nothing if there is no `mmc-pack.json`.
+pub fn update_mmc_pack(multimc_folder: &Path, versions: &PackVersions) -> Result<bool> {
+    let path = multimc_folder.join("mmc-pack.json");
+    if !path.exists() {
+        return Ok(false);
+    }
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut doc: Value = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    let components = doc
+        .get_mut("components")
+        .and_then(|c| c.as_array_mut())
+        .ok_or_else(|| anyhow::anyhow!("{} has no components list", path.display()))?;
+
+    let mut wanted: Vec<(&str, &str, String)> = Vec::new();
+    if let Some(v) = versions.minecraft() {
+        wanted.push(("minecraft", MINECRAFT_UID, v.to_string()));
+    }
+    for (loader, v) in versions.loaders() {
+        // Quilt loads Fabric mods itself, and the launcher refuses an instance with both
+        if loader == Loader::Fabric && versions.loader_version(Loader::Quilt).is_some() {
+            continue;
+        }
+        wanted.push((loader.key(), loader.mmc_uid(), v.to_string()));
+    }
+    let mut changed = false;
+
+    // Drop loaders the pack no longer uses (Minecraft itself always stays)
+    components.retain(|c| {
+        let uid = c.get("uid").and_then(|u| u.as_str()).unwrap_or("");
+        let managed = Loader::ALL.iter().any(|l| l.mmc_uid() == uid);
+        let keep = !managed || wanted.iter().any(|(_, u, _)| *u == uid);
+        if !keep {
+            tracing::info!("removing {} from mmc-pack.json", uid);
+            changed = true;
+        }
+        keep
+    });
+
+    for (key, uid, version) in &wanted {
+        let existing = components
+            .iter_mut()
+            .find(|c| c.get("uid").and_then(|u| u.as_str()) == Some(*uid));
+        match existing {
+            Some(c) => {
+                if c.get("version").and_then(|v| v.as_str()) != Some(version.as_str()) {
+                    tracing::info!("updating {} to {} in mmc-pack.json", key, version);
+                    if let Some(obj) = c.as_object_mut() {
+                        obj.insert("version".into(), Value::String(version.clone()));
+                        // cached values describe the old version; the launcher recomputes them
+                        obj.shift_remove("cachedVersion");
+                        obj.shift_remove("cachedRequires");
+                    }
+                    changed = true;
+                }
+            }
+            None => {
+                tracing::info!("adding {} {} to mmc-pack.json", key, version);
+                let mut obj = Map::new();
+                obj.insert("uid".into(), Value::String(uid.to_string()));
+                obj.insert("version".into(), Value::String(version.clone()));
+                // loaders go after Minecraft, Minecraft goes first
+                let at = if *uid == MINECRAFT_UID {
+                    0
+                } else {
+                    components.len()
+                };
+                components.insert(at, Value::Object(obj));
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        write_atomic(&path, &doc)?;
+    }
+    Ok(changed)
+}
+
+/// Writes via a temporary file and a rename so a crash never leaves a truncated file
+fn write_atomic(path: &Path, doc: &Value) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    {
+        let mut f = std::fs::File::create(&tmp)?;
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+        let mut ser = serde_json::Serializer::with_formatter(&mut f, formatter);
+        serde::Serialize::serialize(doc, &mut ser)?;
+        writeln!(&mut f)?;
+        f.sync_all()?;
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::test_pack::TempDir;
+    use serde_json::json;
+
+    fn versions(toml: &str) -> PackVersions {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn write(dir: &TempDir, components: Value) {
+        let doc = json!({ "components": components, "formatVersion": 1 });
+        std::fs::write(dir.join("mmc-pack.json"), doc.to_string()).unwrap();
+    }
+
+    fn components(dir: &TempDir) -> Value {
+        let doc: Value = serde_json::from_str(&dir.read("mmc-pack.json")).unwrap();
+        assert_eq!(doc["formatVersion"], 1);
+        doc["components"].clone()
+    }
+
+    #[test]
+    fn adds_and_updates_components() {
+        let dir = TempDir::new("mmc-add");
+        write(
+            &dir,
+            json!([
+                { "uid": "org.lwjgl3", "version": "3.3.3" },
+                { "uid": "net.minecraft", "version": "1.20.1", "cachedVersion": "1.20.1", "important": true },
+            ]),
+        );
+        let pack = versions("minecraft = \"1.21.1\"\nfabric = \"0.16.0\"\n");
+        assert!(update_mmc_pack(dir.path(), &pack).unwrap());
+        assert_eq!(
+            components(&dir),
+            json!([
+                { "uid": "org.lwjgl3", "version": "3.3.3" },
+                { "uid": "net.minecraft", "version": "1.21.1", "important": true },
+                { "uid": "net.fabricmc.fabric-loader", "version": "0.16.0" },
+            ])
+        );
+
+        // nothing to do the second time, so no restart either
+        let before = dir.read("mmc-pack.json");
+        assert!(!update_mmc_pack(dir.path(), &pack).unwrap());
+        assert_eq!(dir.read("mmc-pack.json"), before);
+    }
+
+    #[test]
+    fn adds_minecraft_first() {
+        let dir = TempDir::new("mmc-minecraft");
+        write(&dir, json!([{ "uid": "org.lwjgl3", "version": "3.3.3" }]));
+        let pack = versions("minecraft = \"1.21.1\"\n");
+        assert!(update_mmc_pack(dir.path(), &pack).unwrap());
+        assert_eq!(
+            components(&dir),
+            json!([
+                { "uid": "net.minecraft", "version": "1.21.1" },
+                { "uid": "org.lwjgl3", "version": "3.3.3" },
+            ])
+        );
+    }
+
+    #[test]
+    fn removes_loaders_the_pack_dropped() {
+        let dir = TempDir::new("mmc-remove");
+        write(
+            &dir,
+            json!([
+                { "uid": "net.minecraft", "version": "1.20.1" },
+                { "uid": "net.minecraftforge", "version": "47.3.0" },
+            ]),
+        );
+        let pack = versions("minecraft = \"1.20.1\"\nneoforge = \"47.1.106\"\n");
+        assert!(update_mmc_pack(dir.path(), &pack).unwrap());
+        assert_eq!(
+            components(&dir),
+            json!([
+                { "uid": "net.minecraft", "version": "1.20.1" },
+                { "uid": "net.neoforged", "version": "47.1.106" },
+            ])
+        );
+    }
+
+    #[test]
+    fn quilt_replaces_fabric() {
+        let dir = TempDir::new("mmc-quilt");
+        write(
+            &dir,
+            json!([
+                { "uid": "net.minecraft", "version": "1.21.1" },
+                { "uid": "net.fabricmc.fabric-loader", "version": "0.16.0" },
+            ]),
+        );
+        let pack = versions("minecraft = \"1.21.1\"\nfabric = \"0.16.0\"\nquilt = \"0.26.0\"\n");
+        assert!(update_mmc_pack(dir.path(), &pack).unwrap());
+        assert_eq!(
+            components(&dir),
+            json!([
+                { "uid": "net.minecraft", "version": "1.21.1" },
+                { "uid": "org.quiltmc.quilt-loader", "version": "0.26.0" },
+            ])
+        );
+    }
+
+    #[test]
+    fn needs_an_instance() {
+        let dir = TempDir::new("mmc-none");
+        let pack = versions("minecraft = \"1.21.1\"\n");
+        assert!(!update_mmc_pack(dir.path(), &pack).unwrap());
+        assert!(!dir.join("mmc-pack.json").exists());
+        std::fs::write(dir.join("mmc-pack.json"), "{}").unwrap();
+        assert!(update_mmc_pack(dir.path(), &pack).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::errors::HashMismatch;
+use crate::metadata::pack::{Loader, PackVersions};
+
+pub const FABRIC_META_URL: &str = "https://meta.fabricmc.net";
+pub const FORGE_MAVEN_URL: &str = "https://maven.minecraftforge.net";
+pub const NEOFORGE_MAVEN_URL: &str = "https://maven.neoforged.net/releases";
+
+/// Records which loader was installed so unchanged packs skip the step
+const MARKER_FILE: &str = ".packwiz-loader";
+
+/// Where to fetch loader installers from; point these at mirrors for offline setups
+#[derive(Debug, Clone)]
+pub struct LoaderRepos {
+    pub fabric_meta: String,
+    pub forge_maven: String,
+    pub neoforge_maven: String,
+    /// Java executable used to run the Forge/NeoForge installers
+    pub java: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct FabricInstaller {
+    version: String,
+    #[serde(default)]
+    stable: bool,
+}
+
+/// Installs the dedicated server launcher for the pack's loader into `pack_folder`:
+/// the Fabric server launcher jar, or the Forge/NeoForge installer run with
+/// `--installServer`. Installers are downloaded to `cache_dir`.
+pub async fn install_server_loader(
+    versions: &PackVersions,
+    pack_folder: &Path,
+    cache_dir: &Path,
+    repos: &LoaderRepos,
+) -> Result<()> {
+    let minecraft = versions
+        .minecraft()
+        .ok_or_else(|| anyhow::anyhow!("pack.toml does not specify a minecraft version"))?;
+    let Some((mut loader, mut version)) = versions.loader() else {
+        tracing::info!("pack.toml does not specify a mod loader; nothing to install");
+        return Ok(());
+    };
+    // Quilt packs that also list Fabric run on a Fabric server
+    if loader == Loader::Quilt
+        && let Some(fabric) = versions.loader_version(Loader::Fabric)
+    {
+        tracing::info!("installing a Quilt server is not supported; installing Fabric instead");
+        (loader, version) = (Loader::Fabric, fabric);
+    }
+    let marker = pack_folder.join(MARKER_FILE);
+    let wanted = format!("{} {} {}", loader.key(), minecraft, version);
+    if std::fs::read_to_string(&marker).is_ok_and(|m| m.trim() == wanted) {
+        tracing::info!("{} {} server is already installed", loader.key(), version);
+        return Ok(());
+    }
+    match loader {
+        Loader::Fabric => install_fabric(minecraft, version, pack_folder, repos).await?,
+        Loader::Forge => {
+            let url = maven_url(
+                &repos.forge_maven,
+                "net.minecraftforge:forge",
+                &format!("{minecraft}-{version}"),
+                "installer",
+            );
+            run_installer(&url, pack_folder, cache_dir, &repos.java).await?;
+        }
+        Loader::NeoForge => {
+            let url = maven_url(
+                &repos.neoforge_maven,
+                "net.neoforged:neoforge",
+                version,
+                "installer",
+            );
+            run_installer(&url, pack_folder, cache_dir, &repos.java).await?;
+        }
+        Loader::Quilt => anyhow::bail!(
+            "installing a Quilt server is not supported; use the Quilt installer's `install server` command"
+        ),
+    }
+    std::fs::write(&marker, format!("{wanted}\n"))?;
+    tracing::info!("installed {} {} server", loader.key(), version);
+    Ok(())
+}
+
+async fn install_fabric(
+    minecraft: &str,
+    loader: &str,
+    pack_folder: &Path,
+    repos: &LoaderRepos,
+) -> Result<()> {
+    let meta = repos.fabric_meta.trim_end_matches('/');
+    let installers_uri = format!("{meta}/v2/versions/installer");
+    let installers: Vec<FabricInstaller> = serde_json::from_slice(
+        &crate::fetch_bytes_retry(&installers_uri, 3)
+            .await
+            .with_context(|| format!("failed to fetch {installers_uri}"))?,
+    )
+    .with_context(|| "failed to parse Fabric installer versions")?;
+    let installer = installers
+        .iter()
+        .find(|i| i.stable)
+        .or_else(|| installers.first())
+        .ok_or_else(|| anyhow::anyhow!("no Fabric installer versions available"))?;
+    let jar_uri = format!(
+        "{meta}/v2/versions/loader/{minecraft}/{loader}/{}/server/jar",
+        installer.version
+    );
+    let jar = fetch_verified(&jar_uri).await?;
+    std::fs::write(pack_folder.join("fabric-server-launch.jar"), &jar)?;
+    Ok(())
+}
+
+/// The URL of a jar in a Maven repository, `artifact` being `group:name`
+fn maven_url(repo: &str, artifact: &str, version: &str, classifier: &str) -> String {
+    let (group, name) = artifact.split_once(':').unwrap_or(("", artifact));
+    format!(
+        "{}/{}/{name}/{version}/{name}-{version}-{classifier}.jar",
+        repo.trim_end_matches('/'),
+        group.replace('.', "/")
+    )
+}
+
+/// Downloads `url` and checks it against the checksum published next to it,
+/// `<url>.sha256` or else `<url>.sha1`, as Maven repositories do
+async fn fetch_verified(url: &str) -> Result<Bytes> {
+    let jar = crate::fetch_bytes_retry(url, 3)
+        .await
+        .with_context(|| format!("failed to download {url}"))?;
+    let (format, published) = match crate::fetch_bytes(&format!("{url}.sha256")).await {
+        Ok(sum) => ("sha256", sum),
+        Err(_) => {
+            let sum = crate::fetch_bytes_retry(&format!("{url}.sha1"), 3)
+                .await
+                .with_context(|| format!("failed to download the checksum of {url}"))?;
+            ("sha1", sum)
+        }
+    };
+    // checksum files may hold `<hash>  <file name>`
+    let expected = String::from_utf8_lossy(&published)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let got = crate::hash_hex(format, &jar)?;
+    if got != expected {
+        return Err(HashMismatch {
+            subject: url.rsplit('/').next().unwrap_or(url).to_string(),
+            got,
+            expected,
+            format: format.into(),
+        }
+        .into());
+    }
+    Ok(jar)
+}
+
+/// Downloads a Forge-style installer and runs it in `--installServer` mode
+async fn run_installer(url: &str, pack_folder: &Path, cache_dir: &Path, java: &Path) -> Result<()> {
+    let jar = fetch_verified(url).await?;
+    let name = url.rsplit('/').next().unwrap_or(url);
+    std::fs::create_dir_all(cache_dir)
+        .with_context(|| format!("failed to create {}", cache_dir.display()))?;
+    let installer = cache_dir.join(name);
+    std::fs::write(&installer, &jar)?;
+    tracing::info!("running {name}");
+    let mut cmd = std::process::Command::new(java);
+    cmd.arg("-jar")
+        .arg(&installer)
+        .arg("--installServer")
+        .arg(pack_folder)
+        .current_dir(pack_folder);
+    let status = tokio::task::spawn_blocking(move || cmd.status())
+        .await?
+        .with_context(|| format!("failed to run {}", java.display()))?;
+    let _ = std::fs::remove_file(&installer);
+    let _ = std::fs::remove_file(pack_folder.join(format!("{name}.log")));
+    if !status.success() {
+        anyhow::bail!("loader installer exited with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::test_server::{Response, TestServer};
+    use crate::task::test_pack::TempDir;
+
+    fn repos(base: &str) -> LoaderRepos {
+        LoaderRepos {
+            fabric_meta: base.into(),
+            forge_maven: base.into(),
+            neoforge_maven: base.into(),
+            java: "java".into(),
+        }
+    }
+
+    #[test]
+    fn builds_maven_urls() {
+        assert_eq!(
+            maven_url(
+                "https://maven.minecraftforge.net/",
+                "net.minecraftforge:forge",
+                "1.20.1-47.3.0",
+                "installer"
+            ),
+            "https://maven.minecraftforge.net/net/minecraftforge/forge/1.20.1-47.3.0/forge-1.20.1-47.3.0-installer.jar"
+        );
+        assert_eq!(
+            maven_url(
+                NEOFORGE_MAVEN_URL,
+                "net.neoforged:neoforge",
+                "21.1.77",
+                "installer"
+            ),
+            "https://maven.neoforged.net/releases/net/neoforged/neoforge/21.1.77/neoforge-21.1.77-installer.jar"
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_published_checksums() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/good.jar" | "/sha1.jar" | "/bad.jar" => Response::new(200, "jar"),
+            "/good.jar.sha256" => {
+                Response::new(200, format!("{}  good.jar\n", crate::sha256_hex(b"jar")))
+            }
+            "/sha1.jar.sha1" => Response::new(200, crate::sha1_hex(b"jar").to_uppercase()),
+            "/bad.jar.sha256" => Response::new(200, crate::sha256_hex(b"other")),
+            _ => Response::new(404, ""),
+        });
+        assert_eq!(
+            fetch_verified(&server.url("/good.jar")).await.unwrap(),
+            "jar"
+        );
+        assert_eq!(
+            fetch_verified(&server.url("/sha1.jar")).await.unwrap(),
+            "jar"
+        );
+        let err = fetch_verified(&server.url("/bad.jar")).await.unwrap_err();
+        let mismatch = err.downcast_ref::<HashMismatch>().unwrap();
+        assert_eq!(mismatch.subject, "bad.jar");
+        assert_eq!(mismatch.format, "sha256");
+        assert_eq!(crate::exit_code(&err), crate::EXIT_HASH_MISMATCH);
+    }
+
+    #[tokio::test]
+    async fn does_not_install_a_tampered_fabric_launcher() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/v2/versions/installer" => Response::json(&serde_json::json!([
+                { "version": "1.0.1", "stable": true },
+            ])),
+            "/v2/versions/loader/1.21.1/0.16.0/1.0.1/server/jar" => Response::new(200, "launcher"),
+            "/v2/versions/loader/1.21.1/0.16.0/1.0.1/server/jar.sha1" => {
+                Response::new(200, crate::sha1_hex(b"something else"))
+            }
+            _ => Response::new(404, ""),
+        });
+        let dir = TempDir::new("loader-fabric");
+        let versions: PackVersions =
+            toml::from_str("minecraft = \"1.21.1\"\nfabric = \"0.16.0\"\n").unwrap();
+        let err = install_server_loader(
+            &versions,
+            dir.path(),
+            &dir.join("cache"),
+            &repos(&server.url("")),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<HashMismatch>(), "{err:#}");
+        assert!(!dir.join("fabric-server-launch.jar").exists());
+        assert!(!dir.join(MARKER_FILE).exists());
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// A CurseForge file whose author disabled third-party distribution
+#[derive(Debug, Clone, Serialize)]
+pub struct ManualDownload {
+    pub name: String,
+    pub filename: String,
+    pub url: String,
+    /// Destination relative to the pack folder
+    pub dest: String,
+    pub hash_format: String,
+    pub hash: String,
+}
+
+#[derive(Error, Debug)]
+#[error("{} file(s) must be downloaded manually: {}", .0.len(), names(.0))]
+pub struct ManualDownloadsMissing(pub Vec<ManualDownload>);
+
+fn names(missing: &[ManualDownload]) -> String {
+    missing
+        .iter()
+        .map(|m| m.filename.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The browser download folder used when `--downloads-dir` is not given
+pub fn default_downloads_dir() -> Option<PathBuf> {
+    dirs::download_dir()
+}
+
+pub fn print_instructions(pending: &[ManualDownload], downloads_dir: Option<&Path>) {
+    print_list(pending);
+    match downloads_dir {
+        Some(dir) => eprintln!("Save them to {}", dir.display()),
+        None => eprintln!("Copy them into the pack folder at the paths listed in packwiz.json"),
+    }
+}
+
+pub(crate) fn print_list(pending: &[ManualDownload]) {
+    eprintln!(
+        "CurseForge does not allow these files to be downloaded automatically; please download them manually:"
+    );
+    for m in pending {
+        eprintln!("  {}", m.name);
+        eprintln!("    url:      {}", m.url);
+        eprintln!("    filename: {}", m.filename);
+    }
+}
+
+/// Watches `downloads_dir` for the pending files, copying every one whose hash
+/// matches into the pack folder. Waits up to `wait` when running in a terminal,
+/// otherwise looks only once. Returns the files that were installed.
+pub async fn collect(
+    pending: &mut Vec<ManualDownload>,
+    pack_folder: &Path,
+    downloads_dir: Option<&Path>,
+    wait: Duration,
+) -> Result<Vec<ManualDownload>> {
+    let mut installed = Vec::new();
+    let Some(dir) = downloads_dir else {
+        return Ok(installed);
+    };
+    let wait = if std::io::stdin().is_terminal() {
+        wait
+    } else {
+        Duration::ZERO
+    };
+    if !wait.is_zero() {
+        eprintln!(
+            "Waiting up to {}s for the files to appear in {}...",
+            wait.as_secs(),
+            dir.display()
+        );
+    }
+    let started = Instant::now();
+    loop {
+        let mut i = 0;
+        while i < pending.len() {
+            if let Some(found) = find_download(dir, &pending[i]) {
+                let m = pending.remove(i);
+                let dest = pack_folder.join(&m.dest);
+                if let Some(parent) = dest.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(&found, &dest)?;
+                tracing::info!("installed {} from {}", m.filename, found.display());
+                installed.push(m);
+            } else {
+                i += 1;
+            }
+        }
+        if pending.is_empty() || started.elapsed() >= wait {
+            return Ok(installed);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Looks for the expected file, also accepting the "name (1).jar" style
+/// copies browsers create, and checks its hash.
+pub(crate) fn find_download(dir: &Path, m: &ManualDownload) -> Option<PathBuf> {
+    let expected = Path::new(&m.filename);
+    let stem = expected.file_stem()?.to_string_lossy().to_string();
+    let ext = expected
+        .extension()
+        .map(|e| e.to_string_lossy().to_string());
+    let entries = std::fs::read_dir(dir).ok()?;
+    let mut candidates: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            let same_ext = p.extension().map(|e| e.to_string_lossy().to_string()) == ext;
+            let name_stem = p
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            same_ext && name_stem.starts_with(&stem)
+        })
+        .collect();
+    // exact name first
+    candidates.sort_by_key(|p| p.file_name() != Some(expected.as_os_str()));
+    candidates.into_iter().find(|p| {
+        crate::hash_file_hex(&m.hash_format, p).is_ok_and(|h| h.eq_ignore_ascii_case(&m.hash))
+    })
+}
+
+/// Adds the content hash of a manually installed file to its manifest entry,
+/// keeping `linkedFileHash` directly after `hash` as the original installer does.
+pub fn with_linked_hash(value: serde_json::Value, m: &ManualDownload) -> serde_json::Value {
+    let serde_json::Value::Object(obj) = value else {
+        return value;
+    };
+    let mut out = serde_json::Map::new();
+    for (k, v) in obj {
+        let is_hash = k == "hash";
+        out.insert(k, v);
+        if is_hash {
+            out.insert(
+                "linkedFileHash".into(),
+                serde_json::json!({ "type": m.hash_format, "value": m.hash }),
+            );
+        }
+    }
+    serde_json::Value::Object(out)
+}
@@ -1,3 +1,17 @@
+pub mod bundle;
 pub mod cache;
 pub mod download;
+pub mod export;
+pub mod info;
+pub mod launcher;
+pub mod loader;
+pub mod manual;
+pub mod optional;
+pub mod pack_cache;
+pub mod plan;
+pub mod prompt;
+pub mod report;
+#[cfg(test)]
+pub(crate) mod test_pack;
 pub mod update;
+pub mod verify;
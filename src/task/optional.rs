@@ -0,0 +1,564 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::cli::OptionalMode;
+use crate::destination::side::Side;
+use crate::errors::PolicyViolation;
+use crate::metadata::modfile::ModToml;
+use crate::task::download::{EntryContext, PreparedEntry, inclusion, on_side};
+use crate::task::prompt::OptionChoice;
+
+/// Optional mods the user explicitly turned on or off, matched against the
+/// mod name or metafile path. Patterns may use `*`, `?` and `[...]` globs.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OptionSelection {
+    #[serde(default)]
+    pub enable: Vec<String>,
+    #[serde(default)]
+    pub disable: Vec<String>,
+}
+
+impl OptionSelection {
+    pub fn is_empty(&self) -> bool {
+        self.enable.is_empty() && self.disable.is_empty()
+    }
+
+    /// Reads an options file; `.json` files are parsed as JSON, anything else as TOML
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read options file {}", path.display()))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        if is_json {
+            serde_json::from_str(&text)
+                .with_context(|| format!("failed to parse options file {}", path.display()))
+        } else {
+            toml::from_str(&text)
+                .with_context(|| format!("failed to parse options file {}", path.display()))
+        }
+    }
+
+    /// Adds the selections from `other`, which win over ours on conflict
+    pub fn merge(&mut self, other: OptionSelection) {
+        self.enable.retain(|p| !other.disable.contains(p));
+        self.disable.retain(|p| !other.enable.contains(p));
+        self.enable.extend(other.enable);
+        self.disable.extend(other.disable);
+    }
+}
+
+struct OptionalMod<'a> {
+    path: &'a str,
+    name: &'a str,
+    description: &'a str,
+    default_value: bool,
+    side: Side,
+    group: Option<&'a str>,
+}
+
+fn optional_mods(prepared: &[PreparedEntry]) -> Vec<OptionalMod<'_>> {
+    prepared
+        .iter()
+        .filter_map(|p| {
+            let meta = p.metafile.as_ref()?;
+            meta.mod_toml.option.optional.then(|| OptionalMod {
+                path: &p.entry.file,
+                name: &meta.mod_toml.name,
+                description: &meta.mod_toml.option.description,
+                default_value: meta.mod_toml.option.default_value,
+                side: meta.mod_toml.side,
+                group: meta.mod_toml.option.group.as_deref(),
+            })
+        })
+        .collect()
+}
+
+fn matches(pattern: &str, m: &OptionalMod) -> Result<bool> {
+    let opts = MatchOptions {
+        case_sensitive: false,
+        ..MatchOptions::new()
+    };
+    let pat = Pattern::new(pattern)
+        .with_context(|| format!("invalid optional mod pattern: {pattern}"))?;
+    Ok(pat.matches_with(m.name, opts) || pat.matches_with(m.path, opts))
+}
+
+fn available_list(mods: &[OptionalMod]) -> String {
+    if mods.is_empty() {
+        return "  (this pack has no optional mods)".into();
+    }
+    mods.iter()
+        .map(|m| {
+            if m.description.is_empty() {
+                format!("  {} ({})", m.name, m.path)
+            } else {
+                format!("  {} ({}): {}", m.name, m.path, m.description)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Decides whether every optional mod in the pack is enabled, keyed by
+/// metafile path. Explicit selections win over `mode`; in default mode the
+/// choices stored in the previous manifest win over the pack's defaults.
+/// `all` enables a single mod of each group: the first one installed on
+/// `side`, preferring those enabled by default.
+pub fn resolve(
+    prepared: &[PreparedEntry],
+    side: Side,
+    mode: OptionalMode,
+    stored: &BTreeMap<String, bool>,
+    selection: &OptionSelection,
+) -> Result<BTreeMap<String, bool>> {
+    let mods = optional_mods(prepared);
+    let mut values = BTreeMap::new();
+    for m in &mods {
+        let value = match mode {
+            OptionalMode::Default => stored.get(m.path).copied().unwrap_or(m.default_value),
+            OptionalMode::All => true,
+            OptionalMode::None => false,
+        };
+        values.insert(m.path.to_string(), value);
+    }
+    if matches!(mode, OptionalMode::All) {
+        let mut groups: BTreeMap<&str, Vec<&OptionalMod>> = BTreeMap::new();
+        for m in &mods {
+            if let Some(group) = m.group {
+                groups.entry(group).or_default().push(m);
+            }
+        }
+        for members in groups.values() {
+            let installed = || members.iter().filter(|m| on_side(side, m.side));
+            let chosen = installed()
+                .find(|m| m.default_value)
+                .or_else(|| installed().next())
+                .map(|m| m.path);
+            for m in members {
+                values.insert(m.path.to_string(), Some(m.path) == chosen);
+            }
+        }
+    }
+    let explicit = selection
+        .enable
+        .iter()
+        .map(|p| (p, true))
+        .chain(selection.disable.iter().map(|p| (p, false)));
+    for (pattern, value) in explicit {
+        let mut matched = false;
+        for m in &mods {
+            if matches(pattern, m)? {
+                values.insert(m.path.to_string(), value);
+                matched = true;
+            }
+        }
+        if !matched {
+            anyhow::bail!(
+                "no optional mod matches \"{}\"; available optional mods:\n{}",
+                pattern,
+                available_list(&mods)
+            );
+        }
+    }
+    Ok(values)
+}
+
+/// The pack's optional mods with their current selection, for the interactive prompt
+pub fn choices(prepared: &[PreparedEntry], values: &BTreeMap<String, bool>) -> Vec<OptionChoice> {
+    optional_mods(prepared)
+        .into_iter()
+        .map(|m| OptionChoice {
+            path: m.path.to_string(),
+            name: m.name.to_string(),
+            description: m.description.to_string(),
+            default_value: m.default_value,
+            selected: values.get(m.path).copied().unwrap_or(m.default_value),
+        })
+        .collect()
+}
+
+/// Checks the mods that will be installed for the side against the `group`,
+/// `excludes` and `requires` relations of their options, reporting every
+/// conflict at once.
+pub fn validate(prepared: &[PreparedEntry], ctx: &EntryContext) -> Result<()> {
+    let mods: Vec<(&str, &ModToml, bool)> = prepared
+        .iter()
+        .filter_map(|p| {
+            let meta = p.metafile.as_ref()?;
+            let (include_side, include_opt) = inclusion(ctx, &p.entry.file, &meta.mod_toml);
+            Some((
+                p.entry.file.as_str(),
+                &meta.mod_toml,
+                include_side && include_opt,
+            ))
+        })
+        .collect();
+    let refers_to = |reference: &str, path: &str, m: &ModToml| {
+        reference.eq_ignore_ascii_case(&m.name) || reference == path
+    };
+
+    let mut problems = Vec::new();
+    let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (path, m, enabled) in &mods {
+        if !enabled {
+            continue;
+        }
+        if let Some(group) = m.option.group.as_deref() {
+            groups.entry(group).or_default().push(&m.name);
+        }
+        for excluded in &m.option.excludes {
+            for (other_path, other, other_enabled) in &mods {
+                if *other_enabled && other_path != path && refers_to(excluded, other_path, other) {
+                    problems.push(format!(
+                        "{} cannot be enabled together with {}",
+                        m.name, other.name
+                    ));
+                }
+            }
+        }
+        for required in &m.option.requires {
+            let target = mods
+                .iter()
+                .find(|(other_path, other, _)| refers_to(required, other_path, other));
+            match target {
+                Some((_, _, true)) => {}
+                Some((_, other, false)) => problems.push(format!(
+                    "{} requires {}, which is disabled",
+                    m.name, other.name
+                )),
+                None => problems.push(format!(
+                    "{} requires {}, which is not part of this pack",
+                    m.name, required
+                )),
+            }
+        }
+    }
+    for (group, names) in groups {
+        if names.len() > 1 {
+            problems.push(format!(
+                "only one of {} may be enabled (group \"{}\")",
+                names.join(", "),
+                group
+            ));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(PolicyViolation(format!(
+            "optional mod selection has conflicts:\n  - {}",
+            problems.join("\n  - ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::index::IndexEntry;
+    use crate::task::download::Metafile;
+    use crate::task::test_pack::mod_toml;
+
+    /// A metafile `mods/<name>.pw.toml` for side `side`, with `option` appended
+    fn prepared(name: &str, side: &str, option: &str) -> PreparedEntry {
+        let toml = mod_toml(name, "url = \"https://example.com/a.jar\"", option)
+            .replace("side = \"both\"", &format!("side = \"{side}\""));
+        let path = format!("mods/{}.pw.toml", name.to_lowercase().replace(' ', "-"));
+        PreparedEntry {
+            entry: IndexEntry {
+                file: path.clone(),
+                hash_format: None,
+                hash: String::new(),
+                alias: None,
+                metafile: true,
+                preserve: false,
+            },
+            metafile: Some(Metafile {
+                uri: format!("file:///pack/{path}"),
+                mod_toml: toml::from_str(&toml).unwrap(),
+                bytes: toml.into(),
+            }),
+        }
+    }
+
+    fn optional(default: bool, description: &str) -> String {
+        format!(
+            "\n[option]\noptional = true\ndefault = {default}\ndescription = \"{description}\"\n"
+        )
+    }
+
+    fn pack() -> Vec<PreparedEntry> {
+        vec![
+            prepared("Sodium", "client", &optional(false, "Faster rendering")),
+            prepared("Iris Shaders", "client", &optional(false, "")),
+            prepared("JEI", "both", &optional(true, "Recipes")),
+            prepared("Lithium", "both", ""),
+        ]
+    }
+
+    fn select(enable: &[&str], disable: &[&str]) -> OptionSelection {
+        OptionSelection {
+            enable: enable.iter().map(|p| p.to_string()).collect(),
+            disable: disable.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    fn resolve_with(
+        mode: OptionalMode,
+        stored: &[(&str, bool)],
+        selection: OptionSelection,
+    ) -> Result<Vec<(String, bool)>> {
+        let stored = stored.iter().map(|(p, v)| (p.to_string(), *v)).collect();
+        Ok(resolve(&pack(), Side::Client, mode, &stored, &selection)?
+            .into_iter()
+            .collect())
+    }
+
+    fn values(list: &[(&str, bool)]) -> Vec<(String, bool)> {
+        list.iter().map(|(p, v)| (p.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn modes_and_stored_choices() {
+        let defaults = values(&[
+            ("mods/iris-shaders.pw.toml", false),
+            ("mods/jei.pw.toml", true),
+            ("mods/sodium.pw.toml", false),
+        ]);
+        assert_eq!(
+            resolve_with(OptionalMode::Default, &[], OptionSelection::default()).unwrap(),
+            defaults
+        );
+        let stored = [("mods/sodium.pw.toml", true), ("mods/jei.pw.toml", false)];
+        assert_eq!(
+            resolve_with(OptionalMode::Default, &stored, OptionSelection::default()).unwrap(),
+            values(&[
+                ("mods/iris-shaders.pw.toml", false),
+                ("mods/jei.pw.toml", false),
+                ("mods/sodium.pw.toml", true),
+            ])
+        );
+        // stored choices only count in default mode
+        let all = resolve_with(OptionalMode::All, &stored, OptionSelection::default()).unwrap();
+        assert!(all.iter().all(|(_, v)| *v), "{all:?}");
+        let none = resolve_with(OptionalMode::None, &stored, OptionSelection::default()).unwrap();
+        assert!(none.iter().all(|(_, v)| !*v), "{none:?}");
+    }
+
+    #[test]
+    fn selects_by_name_or_path_glob() {
+        let resolved = resolve_with(
+            OptionalMode::Default,
+            &[("mods/iris-shaders.pw.toml", true)],
+            select(&["sod*", "iris ?haders"], &["MODS/JEI.PW.TOML"]),
+        )
+        .unwrap();
+        assert_eq!(
+            resolved,
+            values(&[
+                ("mods/iris-shaders.pw.toml", true),
+                ("mods/jei.pw.toml", false),
+                ("mods/sodium.pw.toml", true),
+            ])
+        );
+        // a mod matched by both lists ends up disabled
+        let resolved = resolve_with(
+            OptionalMode::None,
+            &[],
+            select(&["*"], &["mods/[ij]*.pw.toml"]),
+        )
+        .unwrap();
+        assert_eq!(
+            resolved,
+            values(&[
+                ("mods/iris-shaders.pw.toml", false),
+                ("mods/jei.pw.toml", false),
+                ("mods/sodium.pw.toml", true),
+            ])
+        );
+    }
+
+    #[test]
+    fn unknown_names_list_the_optional_mods() {
+        let err = resolve_with(OptionalMode::Default, &[], select(&["optifine"], &[]))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "no optional mod matches \"optifine\"; available optional mods:\n  \
+             Sodium (mods/sodium.pw.toml): Faster rendering\n  \
+             Iris Shaders (mods/iris-shaders.pw.toml)\n  \
+             JEI (mods/jei.pw.toml): Recipes"
+        );
+        // required mods can't be selected
+        let err = resolve_with(OptionalMode::Default, &[], select(&[], &["Lithium"])).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("no optional mod matches \"Lithium\"")
+        );
+
+        let err = resolve(
+            &[prepared("Lithium", "both", "")],
+            Side::Client,
+            OptionalMode::Default,
+            &BTreeMap::new(),
+            &select(&["sodium"], &[]),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("(this pack has no optional mods)"),
+            "{err}"
+        );
+
+        let err = resolve_with(OptionalMode::Default, &[], select(&["[sodium"], &[])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid optional mod pattern: [sodium");
+    }
+
+    fn context(side: Side, enabled: &[(&str, bool)]) -> EntryContext {
+        EntryContext {
+            pack_folder: Default::default(),
+            index_uri: String::new(),
+            index_hash_format_default: "sha256".into(),
+            side,
+            modrinth_api_base: String::new(),
+            curseforge_urls: Default::default(),
+            option_values: enabled.iter().map(|(p, v)| (p.to_string(), *v)).collect(),
+            installed_dests: Default::default(),
+        }
+    }
+
+    fn conflicts(prepared: &[PreparedEntry], ctx: &EntryContext) -> Vec<String> {
+        match validate(prepared, ctx) {
+            Ok(()) => Vec::new(),
+            Err(err) => {
+                assert!(err.is::<PolicyViolation>(), "{err}");
+                let text = err.to_string();
+                let list = text
+                    .strip_prefix("optional mod selection has conflicts:\n  - ")
+                    .unwrap();
+                list.split("\n  - ").map(str::to_string).collect()
+            }
+        }
+    }
+
+    fn shaders() -> Vec<PreparedEntry> {
+        let member = "\n[option]\noptional = true\ngroup = \"shaders\"\n";
+        vec![
+            prepared("Iris", "client", member),
+            prepared("Oculus", "client", member),
+            prepared("Server Shaders", "server", member),
+        ]
+    }
+
+    #[test]
+    fn one_mod_per_group() {
+        let ctx = context(
+            Side::Client,
+            &[("mods/iris.pw.toml", true), ("mods/oculus.pw.toml", true)],
+        );
+        assert_eq!(
+            conflicts(&shaders(), &ctx),
+            ["only one of Iris, Oculus may be enabled (group \"shaders\")"]
+        );
+        // the server-only member isn't installed on the client
+        let ctx = context(
+            Side::Client,
+            &[
+                ("mods/iris.pw.toml", true),
+                ("mods/server-shaders.pw.toml", true),
+            ],
+        );
+        assert!(conflicts(&shaders(), &ctx).is_empty());
+        // `all` turns on a single member
+        let mut ctx = context(Side::Client, &[]);
+        ctx.option_values = resolve(
+            &shaders(),
+            Side::Client,
+            OptionalMode::All,
+            &BTreeMap::new(),
+            &OptionSelection::default(),
+        )
+        .unwrap();
+        assert!(conflicts(&shaders(), &ctx).is_empty());
+        assert!(ctx.option_values["mods/iris.pw.toml"]);
+        assert!(!ctx.option_values["mods/oculus.pw.toml"]);
+    }
+
+    #[test]
+    fn excludes_and_requires() {
+        let pack = vec![
+            prepared(
+                "OptiFine",
+                "client",
+                "\n[option]\noptional = true\nexcludes = [\"sodium\"]\n",
+            ),
+            prepared("Sodium", "client", "\n[option]\noptional = true\n"),
+            prepared(
+                "Iris",
+                "client",
+                "\n[option]\noptional = true\nrequires = [\"mods/sodium.pw.toml\"]\n",
+            ),
+            prepared("Lithium", "both", "\nrequires = []\n"),
+        ];
+        let ctx = context(
+            Side::Client,
+            &[
+                ("mods/optifine.pw.toml", false),
+                ("mods/sodium.pw.toml", true),
+                ("mods/iris.pw.toml", true),
+            ],
+        );
+        assert!(conflicts(&pack, &ctx).is_empty());
+
+        let ctx = context(
+            Side::Client,
+            &[
+                ("mods/optifine.pw.toml", true),
+                ("mods/sodium.pw.toml", true),
+                ("mods/iris.pw.toml", false),
+            ],
+        );
+        assert_eq!(
+            conflicts(&pack, &ctx),
+            ["OptiFine cannot be enabled together with Sodium"]
+        );
+
+        // every conflict is reported at once
+        let ctx = context(
+            Side::Client,
+            &[
+                ("mods/optifine.pw.toml", true),
+                ("mods/sodium.pw.toml", false),
+                ("mods/iris.pw.toml", true),
+            ],
+        );
+        let mut pack = pack;
+        pack.push(prepared(
+            "Embeddium",
+            "both",
+            "\n[option]\noptional = true\ndefault = true\nrequires = [\"Rubidium\"]\n",
+        ));
+        assert_eq!(
+            conflicts(&pack, &ctx),
+            [
+                "Iris requires Sodium, which is disabled",
+                "Embeddium requires Rubidium, which is not part of this pack",
+            ]
+        );
+        // mods the side doesn't install don't count, whatever their selection
+        let server = context(
+            Side::Server,
+            &[
+                ("mods/optifine.pw.toml", true),
+                ("mods/iris.pw.toml", true),
+                ("mods/embeddium.pw.toml", false),
+            ],
+        );
+        assert!(conflicts(&pack, &server).is_empty());
+    }
+}
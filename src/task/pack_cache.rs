@@ -0,0 +1,173 @@
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::request::auth::redact_url;
+use crate::request::fetcher::{Fetched, Validators};
+
+/// Validators of the pack.toml and index.toml fetched by the last run, kept
+/// next to the manifest (`packwiz.json` → `packwiz.http.json`) so unchanged
+/// files cost a single conditional request. Their contents are kept in the
+/// cache folder, named by sha256.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PackCache {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pack: Option<CachedFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index: Option<CachedFile>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedFile {
+    uri: String,
+    sha256: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Slot {
+    Pack,
+    Index,
+}
+
+impl PackCache {
+    /// Where the validators for `manifest_path` are kept
+    pub fn path(manifest_path: &Path) -> PathBuf {
+        manifest_path.with_extension("http.json")
+    }
+
+    /// Reads the validators stored for `manifest_path`; a missing or broken file is an empty cache
+    pub fn load(manifest_path: &Path, cache_dir: &Path) -> Self {
+        let path = Self::path(manifest_path);
+        let cache = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        PackCache {
+            path,
+            dir: cache_dir.join("pack-files"),
+            ..cache
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_string(self)? + "\n")?;
+        Ok(())
+    }
+
+    fn slot(&mut self, slot: Slot) -> &mut Option<CachedFile> {
+        match slot {
+            Slot::Pack => &mut self.pack,
+            Slot::Index => &mut self.index,
+        }
+    }
+
+    /// Fetches pack.toml or index.toml. A copy from an earlier run is reused
+    /// without asking the server if it has the `expected` hash, or after a
+    /// `304 Not Modified` otherwise.
+    pub async fn fetch(
+        &mut self,
+        slot: Slot,
+        uri: &str,
+        expected: Option<(&str, &str)>,
+    ) -> Result<Bytes> {
+        let dir = self.dir.clone();
+        // stored redacted, so URL passwords don't end up on disk
+        let uri_key = redact_url(uri);
+        let mut cached = self
+            .slot(slot)
+            .take()
+            .filter(|c| c.uri == uri_key)
+            .and_then(|c| Some((read_body(&dir, &c.sha256)?, c)));
+        if let Some((format, hash)) = expected
+            && let Some((body, file)) = cached.take_if(|(body, _)| {
+                crate::hash_hex(format, body).is_ok_and(|h| h.eq_ignore_ascii_case(hash))
+            })
+        {
+            *self.slot(slot) = Some(file);
+            return Ok(body);
+        }
+        let sent = cached
+            .as_ref()
+            .map(|(_, file)| file.validators.clone())
+            .unwrap_or_default();
+        let (body, validators) = match crate::fetch_bytes_if_modified(uri, &sent).await? {
+            Fetched::Modified(body, validators) => (body, validators),
+            Fetched::NotModified => {
+                let (body, file) = cached
+                    .ok_or_else(|| anyhow::anyhow!("unexpected 304 Not Modified for {uri_key}"))?;
+                tracing::debug!("{uri_key} not modified");
+                *self.slot(slot) = Some(file);
+                return Ok(body);
+            }
+        };
+        let sha256 = crate::sha256_hex(&body);
+        match write_body(&dir, &sha256, &body) {
+            Ok(()) => {
+                *self.slot(slot) = Some(CachedFile {
+                    uri: uri_key,
+                    sha256,
+                    validators,
+                })
+            }
+            Err(e) => tracing::warn!("failed to cache {uri_key}: {e:#}"),
+        }
+        Ok(body)
+    }
+}
+
+fn read_body(dir: &Path, sha256: &str) -> Option<Bytes> {
+    let body = std::fs::read(dir.join(sha256)).ok()?;
+    (crate::sha256_hex(&body) == sha256).then(|| Bytes::from(body))
+}
+
+fn write_body(dir: &Path, sha256: &str, body: &[u8]) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(sha256), body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::test_server::{Response, TestServer};
+    use crate::task::test_pack::TempDir;
+
+    #[tokio::test]
+    async fn reuses_the_cached_body_after_not_modified() {
+        let server = TestServer::start(|req| {
+            if req.headers.get("if-none-match").map(String::as_str) == Some("\"v1\"") {
+                return Response::new(304, "");
+            }
+            let mut res = Response::new(200, "name = \"Test\"\n");
+            res.headers.push(("ETag".into(), "\"v1\"".into()));
+            res
+        });
+        let dir = TempDir::new("pack-cache-304");
+        let manifest = dir.join("packwiz.json");
+        let uri = server.url("/pack.toml");
+
+        let mut cache = PackCache::load(&manifest, &dir.join("cache"));
+        let first = cache.fetch(Slot::Pack, &uri, None).await.unwrap();
+        cache.save().unwrap();
+        assert!(dir.join("packwiz.http.json").exists());
+
+        let mut cache = PackCache::load(&manifest, &dir.join("cache"));
+        let second = cache.fetch(Slot::Pack, &uri, None).await.unwrap();
+        assert_eq!(second, first);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].headers.get("if-none-match"), None);
+        assert_eq!(
+            requests[1].headers.get("if-none-match").map(String::as_str),
+            Some("\"v1\"")
+        );
+    }
+}
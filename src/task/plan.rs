@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::collections::BTreeSet;
+
+use crate::metadata::modfile::DownloadMode;
+use crate::task::cache::load_previous;
+use crate::task::download::{Metafile, PreparedEntry, inclusion, manifest_key, metafile_dest};
+use crate::task::report::{FileAction, FileReport, Outcome, PackSummary};
+use crate::task::update::{Options, entry_context, load_pack, prepare_entries};
+
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    /// Index path (the metafile for mods), as used for the manifest
+    pub path: String,
+    /// Location relative to the pack folder
+    pub location: String,
+    pub action: FileAction,
+    pub reason: Option<&'static str>,
+}
+
+/// Works out what an install would do with each file, without downloading or
+/// writing anything
+pub async fn plan(opts: &Options) -> Result<Vec<PlannedFile>> {
+    let loaded = load_pack(&opts.pack_uri).await?;
+    opts.report.pack(PackSummary::new(&loaded));
+    let prev = load_previous(&opts.pack_folder.join(&opts.meta_file));
+    let mut ctx = entry_context(opts, &loaded)?;
+    let prepared = prepare_entries(opts, &mut ctx, &prev, &loaded.index).await?;
+
+    let mut out = Vec::new();
+    let mut keys = BTreeSet::new();
+    for PreparedEntry { entry, metafile } in &prepared {
+        let hash_fmt = entry
+            .hash_format
+            .clone()
+            .unwrap_or_else(|| ctx.index_hash_format_default.clone());
+        let planned = match metafile {
+            Some(Metafile { mod_toml, .. }) => {
+                keys.insert(entry.file.clone());
+                let dest = metafile_dest(entry, mod_toml);
+                let exists = ctx.pack_folder.join(&dest).exists();
+                let (include_side, include_opt) = inclusion(&ctx, &entry.file, mod_toml);
+                let up_to_date = || {
+                    crate::hash_file_hex(
+                        &mod_toml.download.hash_format,
+                        &ctx.pack_folder.join(&dest),
+                    )
+                    .is_ok_and(|h| h == mod_toml.download.hash)
+                };
+                let (action, reason) = if !include_opt {
+                    let action = if exists {
+                        FileAction::Remove
+                    } else {
+                        FileAction::Skip
+                    };
+                    (action, Some("optional mod disabled"))
+                } else if !include_side {
+                    (FileAction::Skip, Some("other side only"))
+                } else if exists && up_to_date() {
+                    (FileAction::Keep, None)
+                } else if matches!(mod_toml.download.mode, DownloadMode::Curseforge) {
+                    (FileAction::Download, Some("from CurseForge"))
+                } else {
+                    (FileAction::Download, None)
+                };
+                PlannedFile {
+                    path: entry.file.clone(),
+                    location: dest,
+                    action,
+                    reason,
+                }
+            }
+            None => {
+                let dest = manifest_key(entry).to_string();
+                keys.insert(dest.clone());
+                let path = ctx.pack_folder.join(&dest);
+                let (action, reason) = if entry.preserve && path.exists() {
+                    (FileAction::Keep, Some("preserved"))
+                } else if crate::hash_file_hex(&hash_fmt, &path).is_ok_and(|h| h == entry.hash) {
+                    (FileAction::Keep, None)
+                } else {
+                    (FileAction::Download, None)
+                };
+                PlannedFile {
+                    path: dest.clone(),
+                    location: dest,
+                    action,
+                    reason,
+                }
+            }
+        };
+        out.push(planned);
+    }
+    for removed in prev.cached_paths.difference(&keys) {
+        let location = prev.locations.get(removed).unwrap_or(removed);
+        if ctx.pack_folder.join(location).exists() {
+            out.push(PlannedFile {
+                path: removed.clone(),
+                location: location.clone(),
+                action: FileAction::Remove,
+                reason: Some("no longer in the pack"),
+            });
+        }
+    }
+    Ok(out)
+}
+
+pub async fn run_plan(opts: Options) -> Result<()> {
+    let planned = plan(&opts).await?;
+    for p in &planned {
+        let mut file = FileReport::new(&p.path, p.action, Outcome::Planned);
+        file.location = (p.location != p.path).then(|| p.location.clone());
+        file.reason = p.reason.map(str::to_string);
+        opts.report.file(file);
+    }
+    if !opts.report.is_text() {
+        return Ok(());
+    }
+    for p in &planned {
+        match p.reason {
+            Some(reason) => println!("{:<9} {} ({})", p.action.as_str(), p.location, reason),
+            None => println!("{:<9} {}", p.action.as_str(), p.location),
+        }
+    }
+    let count = |a: FileAction| planned.iter().filter(|p| p.action == a).count();
+    println!(
+        "{} to download, {} up to date, {} to remove, {} skipped",
+        count(FileAction::Download),
+        count(FileAction::Keep),
+        count(FileAction::Remove),
+        count(FileAction::Skip)
+    );
+    Ok(())
+}
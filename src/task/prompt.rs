@@ -0,0 +1,141 @@
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::{QueueableCommand, cursor};
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct OptionChoice {
+    pub path: String,
+    pub name: String,
+    pub description: String,
+    pub default_value: bool,
+    pub selected: bool,
+}
+
+/// Whether we can show the checklist: both stdin and stderr must be a terminal
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// Shows a checklist of optional mods on stderr. The current selection is
+/// accepted automatically after `timeout` unless a key is pressed first.
+/// Returns `Ok(false)` if the user cancelled the installation.
+pub fn prompt(choices: &mut [OptionChoice], timeout: Duration) -> Result<bool> {
+    terminal::enable_raw_mode()?;
+    let mut err = std::io::stderr();
+    let res = run(&mut err, choices, timeout);
+    terminal::disable_raw_mode()?;
+    err.queue(cursor::Show)?.flush()?;
+    eprintln!();
+    res
+}
+
+fn run(out: &mut impl Write, choices: &mut [OptionChoice], timeout: Duration) -> Result<bool> {
+    let started = Instant::now();
+    let mut countdown = true;
+    let mut pos = 0usize;
+    let mut drawn_lines = 0u16;
+    out.queue(cursor::Hide)?;
+    loop {
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if countdown && remaining.is_zero() {
+            return Ok(true);
+        }
+        drawn_lines = draw(
+            out,
+            choices,
+            pos,
+            countdown.then_some(remaining),
+            drawn_lines,
+        )?;
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        countdown = false;
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => pos = pos.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => pos = (pos + 1).min(choices.len() - 1),
+            KeyCode::Char(' ') => choices[pos].selected = !choices[pos].selected,
+            KeyCode::Char('a') => choices.iter_mut().for_each(|c| c.selected = true),
+            KeyCode::Char('n') => choices.iter_mut().for_each(|c| c.selected = false),
+            KeyCode::Char('d') => choices
+                .iter_mut()
+                .for_each(|c| c.selected = c.default_value),
+            KeyCode::Enter => return Ok(true),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(
+    out: &mut impl Write,
+    choices: &[OptionChoice],
+    pos: usize,
+    remaining: Option<Duration>,
+    previous_lines: u16,
+) -> Result<u16> {
+    if previous_lines > 0 {
+        out.queue(cursor::MoveToPreviousLine(previous_lines))?;
+    }
+    out.queue(cursor::MoveToColumn(0))?
+        .queue(Clear(ClearType::FromCursorDown))?;
+    // Cut lines to the terminal width so wrapping doesn't throw off the redraw
+    let width = terminal::size()
+        .ok()
+        .map(|(w, _)| w as usize)
+        .filter(|w| *w > 1)
+        .unwrap_or(80)
+        - 1;
+    let mut lines = 0u16;
+    let mut line = |out: &mut dyn Write, text: String| -> Result<()> {
+        let text: String = text.chars().take(width).collect();
+        out.queue(Print(text))?.queue(Print("\r\n"))?;
+        lines += 1;
+        Ok(())
+    };
+    line(out, "Optional mods:".into())?;
+    for (i, c) in choices.iter().enumerate() {
+        let marker = if c.selected { "[x]" } else { "[ ]" };
+        let default = if c.default_value { " (default)" } else { "" };
+        if i == pos {
+            out.queue(SetAttribute(Attribute::Reverse))?;
+        }
+        line(out, format!("  {marker} {}{default}", c.name))?;
+        if i == pos {
+            out.queue(SetAttribute(Attribute::Reset))?;
+        }
+        if !c.description.is_empty() {
+            line(out, format!("        {}", c.description))?;
+        }
+    }
+    line(
+        out,
+        "up/down: move  space: toggle  a: all  n: none  d: defaults  enter: continue  q: cancel"
+            .into(),
+    )?;
+    match remaining {
+        Some(r) => line(
+            out,
+            format!(
+                "Continuing with these choices in {}s (press any key to stop the timer)",
+                r.as_secs() + 1
+            ),
+        )?,
+        None => line(out, String::new())?,
+    }
+    out.flush()?;
+    Ok(lines)
+}
@@ -0,0 +1,562 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cli::OutputFormat;
+use crate::errors::{HashMismatch, PolicyViolation, VerificationFailed};
+use crate::request::errors::RequestError;
+use crate::task::manual::{ManualDownload, ManualDownloadsMissing};
+use crate::task::update::LoadedPack;
+
+/// Exit code reported when files still have to be downloaded by hand
+pub const EXIT_MANUAL_DOWNLOAD: i32 = 3;
+/// Exit code reported when downloaded or installed files don't match their hashes
+pub const EXIT_HASH_MISMATCH: i32 = 4;
+/// Exit code reported when a server couldn't be reached or rejected a request
+pub const EXIT_NETWORK: i32 = 5;
+/// Exit code reported when the pack asks for something the installer refuses to do
+pub const EXIT_POLICY: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileAction {
+    Download,
+    Keep,
+    Remove,
+    Skip,
+    /// Has to be downloaded by hand
+    Manual,
+    /// Checked by `verify`
+    Verify,
+}
+
+impl FileAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileAction::Download => "download",
+            FileAction::Keep => "keep",
+            FileAction::Remove => "remove",
+            FileAction::Skip => "skip",
+            FileAction::Manual => "manual",
+            FileAction::Verify => "verify",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Failed,
+    /// Waiting for a manual download
+    Pending,
+    /// `plan` only: nothing was done
+    Planned,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    /// Index path (the metafile for mods)
+    pub path: String,
+    /// Where the file lives in the pack folder, if it differs from `path`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    pub action: FileAction,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    pub fn new(path: impl Into<String>, action: FileAction, outcome: Outcome) -> Self {
+        FileReport {
+            path: path.into(),
+            location: None,
+            action,
+            outcome,
+            reason: None,
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackSummary {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub pack_format: String,
+    pub minecraft: Option<String>,
+    /// Loader key (`forge`, `fabric`, ...) to version
+    pub loaders: BTreeMap<String, String>,
+    pub file_count: usize,
+    pub mod_count: usize,
+}
+
+impl PackSummary {
+    pub fn new(loaded: &LoadedPack) -> Self {
+        let pack = &loaded.pack;
+        let files = &loaded.index.files;
+        PackSummary {
+            name: pack.name.clone(),
+            version: pack.version.clone(),
+            pack_format: pack
+                .pack_format
+                .clone()
+                .unwrap_or_else(|| "packwiz:1.0.0".into()),
+            minecraft: pack.versions.minecraft().map(str::to_string),
+            loaders: pack
+                .versions
+                .loaders()
+                .into_iter()
+                .map(|(l, v)| (l.key().to_string(), v.to_string()))
+                .collect(),
+            file_count: files.len(),
+            mod_count: files.iter().filter(|f| f.metafile).count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    ManualDownload,
+    HashMismatch,
+    Network,
+    Policy,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn of(err: &anyhow::Error) -> Self {
+        let chain = || err.chain();
+        if chain().any(|e| e.is::<ManualDownloadsMissing>()) {
+            ErrorKind::ManualDownload
+        } else if chain().any(|e| e.is::<HashMismatch>() || e.is::<VerificationFailed>()) {
+            ErrorKind::HashMismatch
+        } else if chain().any(|e| e.is::<PolicyViolation>()) {
+            ErrorKind::Policy
+        } else if chain().any(|e| {
+            e.is::<reqwest::Error>()
+                // A malformed URL comes from the pack, not the network
+                || e.downcast_ref::<RequestError>()
+                    .is_some_and(|r| !matches!(r, RequestError::InvalidUrl(_)))
+        }) {
+            ErrorKind::Network
+        } else {
+            ErrorKind::Other
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::ManualDownload => EXIT_MANUAL_DOWNLOAD,
+            ErrorKind::HashMismatch => EXIT_HASH_MISMATCH,
+            ErrorKind::Network => EXIT_NETWORK,
+            ErrorKind::Policy => EXIT_POLICY,
+            ErrorKind::Other => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    /// The file the error belongs to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub message: String,
+}
+
+impl ErrorReport {
+    pub fn new(path: Option<String>, err: &anyhow::Error) -> Self {
+        ErrorReport {
+            kind: ErrorKind::of(err),
+            path,
+            message: format!("{err:#}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Timings {
+    pub total_ms: u64,
+    /// Milliseconds spent in each phase of the run, e.g. `resolve` or `download`
+    pub phases: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+/// The document printed by `--output json`, and the last event of `--output ndjson`
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub command: &'static str,
+    pub status: Status,
+    pub exit_code: i32,
+    pub pack: Option<PackSummary>,
+    pub files: Vec<FileReport>,
+    pub manual_downloads: Vec<ManualDownload>,
+    pub errors: Vec<ErrorReport>,
+    pub timings: Timings,
+    /// Why the pack wasn't checked for updates, when the installed files were only verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offline: Option<String>,
+    /// `config show` only: the effective settings and where they came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<serde_json::Value>,
+}
+
+/// One line of `--output ndjson`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Start { command: &'a str },
+    Pack(&'a PackSummary),
+    File(&'a FileReport),
+    ManualDownload(&'a ManualDownload),
+    Phase { name: &'a str, ms: u64 },
+    Offline { reason: &'a str },
+    Error(&'a ErrorReport),
+    Result(&'a Report),
+}
+
+#[derive(Debug)]
+struct State {
+    report: Report,
+    /// Position of each path in `report.files`
+    file_index: HashMap<String, usize>,
+    started: Instant,
+}
+
+/// Collects the result document of a run and streams events as they happen.
+/// Cheap to clone; clones share the same report.
+#[derive(Debug, Clone)]
+pub struct Reporter {
+    format: OutputFormat,
+    state: Arc<Mutex<State>>,
+}
+
+impl Reporter {
+    pub fn new(format: OutputFormat, command: &'static str) -> Self {
+        let reporter = Reporter {
+            format,
+            state: Arc::new(Mutex::new(State {
+                report: Report {
+                    command,
+                    status: Status::Ok,
+                    exit_code: 0,
+                    pack: None,
+                    files: Vec::new(),
+                    manual_downloads: Vec::new(),
+                    errors: Vec::new(),
+                    timings: Timings::default(),
+                    offline: None,
+                    config: None,
+                },
+                file_index: HashMap::new(),
+                started: Instant::now(),
+            })),
+        };
+        reporter.emit(&Event::Start { command });
+        reporter
+    }
+
+    /// Whether human-readable output should be printed
+    pub fn is_text(&self) -> bool {
+        matches!(self.format, OutputFormat::Text)
+    }
+
+    pub fn pack(&self, summary: PackSummary) {
+        self.emit(&Event::Pack(&summary));
+        self.state.lock().unwrap().report.pack = Some(summary);
+    }
+
+    pub fn config(&self, config: serde_json::Value) {
+        self.state.lock().unwrap().report.config = Some(config);
+    }
+
+    /// Records that no update check happened, and why
+    pub fn offline(&self, reason: &str) {
+        self.emit(&Event::Offline { reason });
+        self.state.lock().unwrap().report.offline = Some(reason.to_string());
+    }
+
+    /// Records a file, replacing an earlier record for the same path
+    pub fn file(&self, file: FileReport) {
+        self.emit(&Event::File(&file));
+        let state = &mut *self.state.lock().unwrap();
+        let files = &mut state.report.files;
+        match state.file_index.get(&file.path) {
+            Some(&i) => files[i] = file,
+            None => {
+                state.file_index.insert(file.path.clone(), files.len());
+                files.push(file);
+            }
+        }
+    }
+
+    pub fn manual_download(&self, download: &ManualDownload) {
+        self.emit(&Event::ManualDownload(download));
+        self.state
+            .lock()
+            .unwrap()
+            .report
+            .manual_downloads
+            .push(download.clone());
+    }
+
+    pub fn error(&self, path: Option<String>, err: &anyhow::Error) {
+        let error = ErrorReport::new(path, err);
+        self.emit(&Event::Error(&error));
+        self.state.lock().unwrap().report.errors.push(error);
+    }
+
+    /// Records how long a phase took, measured from `since`
+    pub fn phase(&self, name: &str, since: Instant) {
+        let ms = duration_ms(since.elapsed());
+        self.emit(&Event::Phase { name, ms });
+        self.state
+            .lock()
+            .unwrap()
+            .report
+            .timings
+            .phases
+            .insert(name.to_string(), ms);
+    }
+
+    /// A copy of the report as collected so far
+    pub fn report(&self) -> Report {
+        self.state.lock().unwrap().report.clone()
+    }
+
+    /// Completes the report with the run's result and prints it; returns the exit code
+    pub fn finish(&self, result: &anyhow::Result<()>) -> i32 {
+        if let Err(err) = result {
+            let message = format!("{err:#}");
+            // errors already reported for a single file aren't repeated
+            let reported = self
+                .state
+                .lock()
+                .unwrap()
+                .report
+                .errors
+                .iter()
+                .any(|e| e.message == message);
+            if !reported {
+                self.error(None, err);
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        state.report.timings.total_ms = duration_ms(state.started.elapsed());
+        if let Err(err) = result {
+            state.report.status = Status::Error;
+            state.report.exit_code = ErrorKind::of(err).exit_code();
+        }
+        let report = &state.report;
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Json => {
+                let mut out = std::io::stdout().lock();
+                let _ = serde_json::to_writer_pretty(&mut out, report);
+                let _ = writeln!(out);
+            }
+            OutputFormat::Ndjson => self.emit(&Event::Result(report)),
+        }
+        report.exit_code
+    }
+
+    fn emit(&self, event: &Event) {
+        if !matches!(self.format, OutputFormat::Ndjson) {
+            return;
+        }
+        let mut out = std::io::stdout().lock();
+        let _ = serde_json::to_writer(&mut out, event);
+        let _ = writeln!(out);
+        let _ = out.flush();
+    }
+}
+
+fn duration_ms(d: Duration) -> u64 {
+    d.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn manual() -> ManualDownload {
+        ManualDownload {
+            name: "B".into(),
+            filename: "b.jar".into(),
+            url: "https://www.curseforge.com/minecraft/mc-mods/b/download/200".into(),
+            dest: "mods/b.jar".into(),
+            hash_format: "sha1".into(),
+            hash: "0".repeat(40),
+        }
+    }
+
+    #[test]
+    fn serializes_a_run() {
+        let reporter = Reporter::new(OutputFormat::Text, "install");
+        let mut file = FileReport::new("mods/a.pw.toml", FileAction::Download, Outcome::Ok);
+        file.location = Some("mods/a.jar".into());
+        reporter.file(file);
+        reporter.file(FileReport::new(
+            "config/a.txt",
+            FileAction::Keep,
+            Outcome::Ok,
+        ));
+        // a later record for the same path replaces the first
+        let mut pending = FileReport::new("mods/b.pw.toml", FileAction::Download, Outcome::Ok);
+        reporter.file(pending.clone());
+        pending.action = FileAction::Manual;
+        pending.outcome = Outcome::Pending;
+        pending.reason = Some("CurseForge does not allow automatic downloads".into());
+        reporter.file(pending);
+        reporter.manual_download(&manual());
+        reporter.phase("download", Instant::now());
+
+        let err = anyhow::Error::new(ManualDownloadsMissing(vec![manual()]));
+        let code = reporter.finish(&Err(err));
+        assert_eq!(code, EXIT_MANUAL_DOWNLOAD);
+
+        let mut report = serde_json::to_value(reporter.report()).unwrap();
+        assert!(report["timings"]["total_ms"].is_u64());
+        assert!(report["timings"]["phases"]["download"].is_u64());
+        report.as_object_mut().unwrap().remove("timings");
+        assert_eq!(
+            report,
+            json!({
+                "command": "install",
+                "status": "error",
+                "exit_code": 3,
+                "pack": null,
+                "files": [
+                    { "path": "mods/a.pw.toml", "location": "mods/a.jar", "action": "download", "outcome": "ok" },
+                    { "path": "config/a.txt", "action": "keep", "outcome": "ok" },
+                    {
+                        "path": "mods/b.pw.toml",
+                        "action": "manual",
+                        "outcome": "pending",
+                        "reason": "CurseForge does not allow automatic downloads",
+                    },
+                ],
+                "manual_downloads": [{
+                    "name": "B",
+                    "filename": "b.jar",
+                    "url": "https://www.curseforge.com/minecraft/mc-mods/b/download/200",
+                    "dest": "mods/b.jar",
+                    "hash_format": "sha1",
+                    "hash": "0".repeat(40),
+                }],
+                "errors": [{
+                    "kind": "manual_download",
+                    "message": "1 file(s) must be downloaded manually: b.jar",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn serializes_events() {
+        let file = FileReport::new("config/a.txt", FileAction::Remove, Outcome::Ok);
+        assert_eq!(
+            serde_json::to_value(Event::File(&file)).unwrap(),
+            json!({ "event": "file", "path": "config/a.txt", "action": "remove", "outcome": "ok" })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::Offline {
+                reason: "--offline was given"
+            })
+            .unwrap(),
+            json!({ "event": "offline", "reason": "--offline was given" })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::Phase {
+                name: "download",
+                ms: 5
+            })
+            .unwrap(),
+            json!({ "event": "phase", "name": "download", "ms": 5 })
+        );
+    }
+
+    #[test]
+    fn maps_errors_to_exit_codes() {
+        let hash = || HashMismatch {
+            subject: "mod A".into(),
+            got: "1".into(),
+            expected: "2".into(),
+            format: "sha1".into(),
+        };
+        let cases: Vec<(anyhow::Error, ErrorKind, i32)> = vec![
+            (
+                ManualDownloadsMissing(vec![manual()]).into(),
+                ErrorKind::ManualDownload,
+                3,
+            ),
+            (hash().into(), ErrorKind::HashMismatch, 4),
+            (VerificationFailed(2).into(), ErrorKind::HashMismatch, 4),
+            (
+                RequestError::Server {
+                    service: "curseforge",
+                    status: 502,
+                    body: String::new(),
+                }
+                .into(),
+                ErrorKind::Network,
+                5,
+            ),
+            (
+                RequestError::InvalidUrl("::".into()).into(),
+                ErrorKind::Other,
+                1,
+            ),
+            (PolicyViolation("no".into()).into(), ErrorKind::Policy, 6),
+            (anyhow::anyhow!("something else"), ErrorKind::Other, 1),
+            // the kind is found anywhere in the chain
+            (
+                anyhow::Error::new(hash()).context("failed to install mod A"),
+                ErrorKind::HashMismatch,
+                4,
+            ),
+        ];
+        for (err, kind, code) in cases {
+            assert_eq!(ErrorKind::of(&err), kind, "{err:#}");
+            assert_eq!(kind.exit_code(), code);
+            let reporter = Reporter::new(OutputFormat::Text, "install");
+            assert_eq!(reporter.finish(&Err(err)), code);
+            let report = reporter.report();
+            assert_eq!(report.exit_code, code);
+            assert_eq!(report.errors.len(), 1);
+        }
+
+        let reporter = Reporter::new(OutputFormat::Text, "verify");
+        assert_eq!(reporter.finish(&Ok(())), 0);
+        let report = serde_json::to_value(reporter.report()).unwrap();
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["errors"], json!([]));
+    }
+
+    #[test]
+    fn file_errors_are_not_repeated() {
+        let reporter = Reporter::new(OutputFormat::Text, "install");
+        let err = anyhow::anyhow!("failed to download a.jar");
+        reporter.error(Some("mods/a.pw.toml".into()), &err);
+        reporter.finish(&Err(err));
+        let errors = serde_json::to_value(reporter.report().errors).unwrap();
+        assert_eq!(
+            errors,
+            json!([{ "kind": "other", "path": "mods/a.pw.toml", "message": "failed to download a.jar" }])
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Packs written to a temporary folder, and install options for them, for tests
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::{OptionalMode, OutputFormat};
+use crate::destination::side::Side;
+use crate::task::optional::OptionSelection;
+use crate::task::report::Reporter;
+use crate::task::update::Options;
+
+/// A folder under the system temp dir, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// An empty folder; `name` has to be unique among the tests
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "packwiz-installer-test-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.join(path)).unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+struct Entry {
+    path: String,
+    data: Bytes,
+    metafile: bool,
+    preserve: bool,
+}
+
+/// Builds pack.toml, index.toml (sha256) and the files they list
+#[derive(Default)]
+pub struct TestPack {
+    versions: String,
+    entries: Vec<Entry>,
+    /// Files served next to the pack but not listed in the index
+    extra: Vec<(String, Bytes)>,
+}
+
+impl TestPack {
+    pub fn new() -> Self {
+        TestPack {
+            versions: "minecraft = \"1.21.1\"\n".into(),
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the `[versions]` table
+    pub fn versions(mut self, versions: &str) -> Self {
+        self.versions = versions.into();
+        self
+    }
+
+    /// A plain file
+    pub fn file(self, path: &str, data: &str) -> Self {
+        self.entry(path, data, false, false)
+    }
+
+    /// A plain file with `preserve = true`
+    pub fn preserved(self, path: &str, data: &str) -> Self {
+        self.entry(path, data, false, true)
+    }
+
+    /// A `.pw.toml` metafile
+    pub fn metafile(self, path: &str, toml: &str) -> Self {
+        self.entry(path, toml, true, false)
+    }
+
+    /// A mod `mods/<name>.pw.toml` installing `mods/<name>.jar`, whose content
+    /// is `name` and is served at `jars/<name>.jar`. `extra` is appended to the
+    /// metafile.
+    pub fn jar(self, name: &str, extra: &str) -> Self {
+        let toml = mod_toml(name, &format!("url = \"../jars/{name}.jar\""), extra);
+        self.metafile(&format!("mods/{name}.pw.toml"), &toml)
+            .extra(&format!("jars/{name}.jar"), name)
+    }
+
+    /// A file that is served but not listed in the index
+    pub fn extra(mut self, path: &str, data: &str) -> Self {
+        self.extra
+            .push((path.into(), Bytes::from(data.to_string())));
+        self
+    }
+
+    fn entry(mut self, path: &str, data: &str, metafile: bool, preserve: bool) -> Self {
+        self.entries.push(Entry {
+            path: path.into(),
+            data: Bytes::from(data.to_string()),
+            metafile,
+            preserve,
+        });
+        self
+    }
+
+    /// Every file of the pack by path, pack.toml and index.toml included
+    pub fn files(&self) -> Vec<(String, Bytes)> {
+        let mut index = String::from("hash-format = \"sha256\"\n");
+        for e in &self.entries {
+            index.push_str(&format!(
+                "\n[[files]]\nfile = \"{}\"\nhash = \"{}\"\nmetafile = {}\npreserve = {}\n",
+                e.path,
+                crate::sha256_hex(&e.data),
+                e.metafile,
+                e.preserve
+            ));
+        }
+        let pack = format!(
+            "name = \"Test\"\nversion = \"1.0.0\"\npack-format = \"packwiz:1.1.0\"\n\n\
+             [index]\nfile = \"index.toml\"\nhash-format = \"sha256\"\nhash = \"{}\"\n\n\
+             [versions]\n{}",
+            crate::sha256_hex(index.as_bytes()),
+            self.versions
+        );
+        let mut files: Vec<(String, Bytes)> = self
+            .entries
+            .iter()
+            .map(|e| (e.path.clone(), e.data.clone()))
+            .chain(self.extra.iter().cloned())
+            .collect();
+        files.push(("index.toml".into(), Bytes::from(index)));
+        files.push(("pack.toml".into(), Bytes::from(pack)));
+        files
+    }
+
+    /// Writes the pack to `dir` and returns the `file://` URL of its pack.toml
+    pub fn write(&self, dir: &Path) -> String {
+        for (path, data) in self.files() {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+        reqwest::Url::from_file_path(dir.join("pack.toml"))
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Every file in a zip archive by path
+pub fn read_zip(path: &Path) -> HashMap<String, Bytes> {
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+    (0..zip.len())
+        .map(|i| {
+            let mut file = zip.by_index(i).unwrap();
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut file, &mut data).unwrap();
+            (file.name().to_string(), Bytes::from(data))
+        })
+        .collect()
+}
+
+/// A metafile for `<name>.jar` whose content is `name`, hashed with sha1
+pub fn mod_toml(name: &str, download: &str, extra: &str) -> String {
+    format!(
+        "name = \"{name}\"\nfilename = \"{name}.jar\"\nside = \"both\"\n\n\
+         [download]\n{download}\nhash-format = \"sha1\"\nhash = \"{}\"\n{extra}",
+        crate::sha1_hex(name.as_bytes())
+    )
+}
+
+/// Options installing `pack_uri` into `<dir>/pack` for the client, without
+/// prompting or waiting for anything
+pub fn options(pack_uri: &str, dir: &Path) -> Options {
+    Options {
+        pack_uri: pack_uri.into(),
+        side: Side::Client,
+        optional_mode: OptionalMode::Default,
+        option_selection: OptionSelection::default(),
+        prompt_timeout: None,
+        pack_folder: dir.join("pack"),
+        multimc_folder: Some(dir.join("pack")),
+        meta_file: "packwiz.json".into(),
+        modrinth_api_base: crate::MODRINTH_API_BASE.into(),
+        curseforge: crate::CurseForgeApi {
+            base_url: crate::CURSEFORGE_API_BASE.into(),
+            api_key: Some(crate::request::secret::Secret::new("test-key")),
+            api_key_file: None,
+            user_agent: "test".into(),
+        },
+        downloads_dir: Some(dir.join("downloads")),
+        manual_wait: Duration::ZERO,
+        loader: None,
+        concurrency: 4,
+        cache_dir: dir.join("cache"),
+        offline: false,
+        report: Reporter::new(OutputFormat::Text, "install"),
+    }
+}
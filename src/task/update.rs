@@ -1,93 +1,372 @@
 use anyhow::{Context, Result};
+use bytes::Bytes;
 use futures::{StreamExt, stream};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::errors::HashMismatch;
 use crate::metadata::index::IndexToml;
 use crate::metadata::pack::PackFile as PackFileToml;
-use crate::task::cache::{load_previous, remove_unreferenced};
-use crate::task::download::{EntryContext, process_entry};
+use crate::request::archive::Mount;
+use crate::task::cache::{PreviousManifest, load_previous, remove_unreferenced};
+use crate::task::download::{
+    EntryContext, EntryResult, PreparedEntry, inclusion, manifest_key, metafile_dest,
+    prepare_entry, process_entry,
+};
+use crate::task::loader::{LoaderRepos, install_server_loader};
+use crate::task::manual::{self, ManualDownload, ManualDownloadsMissing};
+use crate::task::optional::{self, OptionSelection};
+use crate::task::pack_cache::{PackCache, Slot};
+use crate::task::prompt;
+use crate::task::report::{FileAction, FileReport, Outcome, PackSummary, Reporter};
 
 #[derive(Debug, Clone)]
 pub struct Options {
     pub pack_uri: String,
     pub side: crate::destination::side::Side,
     pub optional_mode: crate::cli::OptionalMode,
+    pub option_selection: OptionSelection,
+    /// Show the optional mod checklist, auto-accepting after this long; `None` skips it
+    pub prompt_timeout: Option<Duration>,
     pub pack_folder: PathBuf,
+    /// MultiMC/Prism instance folder holding `mmc-pack.json` (defaults to the pack folder's parent)
+    pub multimc_folder: Option<PathBuf>,
     pub meta_file: String,
+    pub modrinth_api_base: String,
+    pub curseforge: crate::CurseForgeApi,
+    /// Where to look for manually downloaded files (defaults to the user's downloads folder)
+    pub downloads_dir: Option<PathBuf>,
+    pub manual_wait: Duration,
+    /// Install the pack's mod loader server into the pack folder after updating
+    pub loader: Option<LoaderRepos>,
+    /// How many files are fetched at the same time
+    pub concurrency: usize,
+    /// Folder for the installer's cache
+    pub cache_dir: PathBuf,
+    /// Verify the installed pack instead of fetching it
+    pub offline: bool,
+    /// Collects the per-file results for `--output json|ndjson`
+    pub report: Reporter,
 }
 
-pub async fn run_update(opts: Options) -> Result<()> {
+/// pack.toml and index.toml, fetched and checked against the index hash
+#[derive(Debug, Clone)]
+pub struct LoadedPack {
+    pub pack_hash_sha256: String,
+    pub pack: PackFileToml,
+    /// pack.toml as fetched
+    pub pack_bytes: Bytes,
+    pub index_uri: String,
+    pub index_hash_format: String,
+    pub index_hash: Option<String>,
+    pub index: IndexToml,
+    /// index.toml as fetched
+    pub index_bytes: Bytes,
+    /// Keeps the pack archive the pack was read from mounted while the pack is in use
+    pub archive: Option<Arc<Mount>>,
+}
+
+/// Fetches and validates pack.toml and its index, e.g. for launchers that need the
+/// pack's Minecraft and loader versions (`loaded.pack.versions.minecraft()`).
+/// Pack archives and .mrpack files are opened first.
+pub async fn load_pack(pack_uri: &str) -> Result<LoadedPack> {
+    load_pack_cached(pack_uri, None).await
+}
+
+/// [`load_pack`], reusing the copies in `cache` when the server says they're unchanged
+pub async fn load_pack_cached(
+    pack_uri: &str,
+    mut cache: Option<&mut PackCache>,
+) -> Result<LoadedPack> {
+    // A zip or tarball of the pack is read in one go; everything else is looked up inside it
+    let archive = if crate::request::archive::is_archive(pack_uri) {
+        Some(Arc::new(crate::request::archive::mount(pack_uri).await?))
+    } else {
+        None
+    };
+    let pack_uri = archive.as_ref().map_or(pack_uri, |a| a.url());
     // Fetch pack.toml
-    let pack_bytes = crate::fetch_bytes(&opts.pack_uri)
-        .await
-        .with_context(|| format!("failed to fetch pack file: {}", opts.pack_uri))?;
+    let pack_bytes = match cache.as_deref_mut() {
+        Some(cache) => cache.fetch(Slot::Pack, pack_uri, None).await,
+        None => crate::fetch_bytes(pack_uri).await,
+    };
+    let pack_bytes = pack_bytes.with_context(|| {
+        format!(
+            "failed to fetch pack file: {}",
+            crate::request::auth::redact_url(pack_uri)
+        )
+    })?;
     let pack_hash_sha256 = super_hash_sha256(&pack_bytes);
     let pack_toml: PackFileToml = toml::from_str(std::str::from_utf8(&pack_bytes)?)
         .with_context(|| "failed to parse pack.toml")?;
-
-    // Prepare paths
-    if !opts.pack_folder.exists() {
-        std::fs::create_dir_all(&opts.pack_folder)?;
-    }
-    let manifest_path = opts.pack_folder.join(&opts.meta_file);
-
-    // Load previous manifest for cleanup
-    let prev = load_previous(&manifest_path);
+    pack_toml.validate()?;
 
     // Load index
     let (index_uri, index_hash_format, index_hash_expected) =
         if let Some(idx) = pack_toml.index.clone() {
-            let file_uri = crate::join_uri(&opts.pack_uri, &idx.file)?;
+            let file_uri = crate::join_uri(pack_uri, &idx.file)?;
             let fmt = idx.hash_format.unwrap_or_else(|| "sha256".into());
             let h = idx.hash;
             (file_uri, fmt, h)
         } else {
             anyhow::bail!("pack.toml is missing [index]")
         };
-    let index_bytes = crate::fetch_bytes(&index_uri)
-        .await
-        .with_context(|| "failed to fetch index file")?;
+    let expected = index_hash_expected
+        .as_deref()
+        .map(|h| (index_hash_format.as_str(), h));
+    let index_bytes = match cache {
+        Some(cache) => cache.fetch(Slot::Index, &index_uri, expected).await,
+        None => crate::fetch_bytes(&index_uri).await,
+    }
+    .with_context(|| "failed to fetch index file")?;
     if let Some(exp) = index_hash_expected.as_ref() {
         let got = crate::hash_hex(&index_hash_format, &index_bytes)?;
         if &got != exp {
-            anyhow::bail!(
-                "index hash mismatch: got {}, expected {} (format {})",
+            return Err(HashMismatch {
+                subject: "index.toml".into(),
                 got,
-                exp,
-                index_hash_format
-            );
+                expected: exp.clone(),
+                format: index_hash_format,
+            }
+            .into());
         }
     }
     let index_toml: IndexToml = toml::from_str(std::str::from_utf8(&index_bytes)?)
         .with_context(|| "failed to parse index.toml")?;
+    Ok(LoadedPack {
+        pack_hash_sha256,
+        pack: pack_toml,
+        pack_bytes,
+        index_uri,
+        index_hash_format,
+        index_hash: index_hash_expected,
+        index: index_toml,
+        index_bytes,
+        archive,
+    })
+}
 
-    // Process entries in parallel
-    let http = crate::request::client::build_http_client()?;
-    let ctx = EntryContext {
+pub(crate) fn entry_context(opts: &Options, loaded: &LoadedPack) -> Result<EntryContext> {
+    Ok(EntryContext {
         pack_folder: opts.pack_folder.clone(),
-        index_uri: index_uri.clone(),
-        index_hash_format_default: index_toml.hash_format.clone(),
+        index_uri: loaded.index_uri.clone(),
+        index_hash_format_default: loaded.index.hash_format.clone(),
         side: opts.side,
-        optional_mode: opts.optional_mode,
-        http,
-    };
-    let futs = index_toml
+        modrinth_api_base: opts.modrinth_api_base.clone(),
+        curseforge_urls: HashMap::new(),
+        option_values: BTreeMap::new(),
+        installed_dests: HashSet::new(),
+    })
+}
+
+/// Fetches every metafile and settles which optional mods are enabled,
+/// asking the user if allowed, before anything is downloaded.
+pub(crate) async fn prepare_entries(
+    opts: &Options,
+    ctx: &mut EntryContext,
+    prev: &PreviousManifest,
+    index: &IndexToml,
+) -> Result<Vec<PreparedEntry>> {
+    let prepare_futs = index
         .files
         .clone()
         .into_iter()
-        .map(|e| process_entry(e, &ctx));
-    let results: Vec<_> = stream::iter(futs).buffer_unordered(8).collect().await;
+        .map(|e| prepare_entry(e, ctx));
+    let prepared = stream::iter(prepare_futs)
+        .buffered(opts.concurrency)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    ctx.option_values = optional::resolve(
+        &prepared,
+        opts.side,
+        opts.optional_mode,
+        &prev.option_values,
+        &opts.option_selection,
+    )?;
+    // A selection that can't be installed fails before anyone is asked anything
+    optional::validate(&prepared, ctx)?;
+    if let Some(timeout) = opts.prompt_timeout
+        && !timeout.is_zero()
+        && prompt::is_interactive()
+    {
+        let choices = optional::choices(&prepared, &ctx.option_values);
+        if !choices.is_empty() {
+            // The prompt blocks on the terminal; it runs off the async threads
+            let (choices, confirmed) = tokio::task::spawn_blocking(move || {
+                let mut choices = choices;
+                let confirmed = prompt::prompt(&mut choices, timeout);
+                (choices, confirmed)
+            })
+            .await?;
+            if !confirmed? {
+                anyhow::bail!("installation cancelled");
+            }
+            for c in choices {
+                ctx.option_values.insert(c.path, c.selected);
+            }
+            optional::validate(&prepared, ctx)?;
+        }
+    }
+    ctx.installed_dests = prepared
+        .iter()
+        .filter_map(|p| {
+            let meta = p.metafile.as_ref()?;
+            let (include_side, include_opt) = inclusion(ctx, &p.entry.file, &meta.mod_toml);
+            (include_side && include_opt).then(|| metafile_dest(&p.entry, &meta.mod_toml))
+        })
+        .collect();
+    Ok(prepared)
+}
+
+pub async fn run_update(opts: Options) -> Result<()> {
+    let report = &opts.report;
+    let started = Instant::now();
+    let manifest_path = opts.pack_folder.join(&opts.meta_file);
+    if opts.offline {
+        return run_offline(&opts, "--offline was given");
+    }
+    let mut pack_cache = PackCache::load(&manifest_path, &opts.cache_dir);
+    let loaded = match load_pack_cached(&opts.pack_uri, Some(&mut pack_cache)).await {
+        Ok(loaded) => loaded,
+        Err(e) if is_unreachable(&e) && manifest_path.exists() => {
+            let reason = format!("the pack host could not be reached: {e:#}");
+            return run_offline(&opts, &reason)
+                .map_err(|v| e.context(format!("the installed pack can't be used: {v:#}")));
+        }
+        Err(e) => return Err(e),
+    };
+    report.pack(PackSummary::new(&loaded));
+    report.phase("load_pack", started);
+    let LoadedPack {
+        pack_hash_sha256,
+        pack: pack_toml,
+        index_hash_format,
+        index_hash: index_hash_expected,
+        index: index_toml,
+        ..
+    } = loaded.clone();
+
+    // Prepare paths
+    if !opts.pack_folder.exists() {
+        std::fs::create_dir_all(&opts.pack_folder)?;
+    }
+
+    // Load previous manifest for cleanup
+    let prev = load_previous(&manifest_path);
+    if up_to_date(&opts, &prev, &loaded) {
+        tracing::info!("Modpack is already up to date!");
+        let started = Instant::now();
+        save_pack_cache(&pack_cache);
+        finish_instance(&opts, &pack_toml).await?;
+        report.phase("finalize", started);
+        return Ok(());
+    }
+
+    // Fetch metafiles first so CurseForge files can be resolved in one batch
+    let started = Instant::now();
+    let mut ctx = entry_context(&opts, &loaded)?;
+    let prepared = prepare_entries(&opts, &mut ctx, &prev, &index_toml).await?;
+    let cf_ids: Vec<(i64, i64)> = prepared
+        .iter()
+        .filter_map(|p| p.curseforge_ids(&ctx))
+        .collect();
+    if !cf_ids.is_empty() {
+        ctx.curseforge_urls = crate::cf_get_download_urls(&opts.curseforge, &cf_ids)
+            .await
+            .with_context(|| "failed to resolve CurseForge downloads")?;
+    }
+    report.phase("resolve", started);
+
+    let started = Instant::now();
+    let futs = prepared.into_iter().map(|p| {
+        let path = p.entry.file.clone();
+        let ctx = &ctx;
+        async move {
+            let result = process_entry(p, ctx).await;
+            match &result {
+                Ok(Some(er)) => report.file(file_report(er)),
+                Ok(None) => {}
+                Err(e) => {
+                    let mut failed = FileReport::new(&path, FileAction::Download, Outcome::Failed);
+                    failed.error = Some(format!("{e:#}"));
+                    report.file(failed);
+                    report.error(Some(path), e);
+                }
+            }
+            result
+        }
+    });
+    let results: Vec<_> = stream::iter(futs)
+        .buffer_unordered(opts.concurrency)
+        .collect()
+        .await;
+    report.phase("download", started);
     // Collect results into a lookup to allow insertion in index order
-    let mut by_path: std::collections::HashMap<String, serde_json::Value> =
-        std::collections::HashMap::new();
+    let mut by_path: HashMap<String, serde_json::Value> = HashMap::new();
     let mut new_paths: BTreeSet<String> = BTreeSet::new();
+    let mut pending: Vec<ManualDownload> = Vec::new();
+    let mut manual_keys: HashMap<String, String> = HashMap::new();
+    let mut first_error = None;
     for r in results {
-        if let Some(er) = r? {
-            new_paths.insert(er.path.clone());
-            by_path.insert(er.path, er.value);
+        match r {
+            Ok(Some(er)) => {
+                if let Some(m) = er.manual {
+                    manual_keys.insert(m.dest.clone(), er.path.clone());
+                    pending.push(m);
+                }
+                new_paths.insert(er.path.clone());
+                by_path.insert(er.path, er.value);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    // Ask for files that CurseForge won't let us download, then pick them up
+    if !pending.is_empty() {
+        let started = Instant::now();
+        for m in &pending {
+            report.manual_download(m);
+        }
+        let downloads_dir = opts
+            .downloads_dir
+            .clone()
+            .or_else(manual::default_downloads_dir);
+        manual::print_instructions(&pending, downloads_dir.as_deref());
+        let installed = manual::collect(
+            &mut pending,
+            &opts.pack_folder,
+            downloads_dir.as_deref(),
+            opts.manual_wait,
+        )
+        .await?;
+        for m in installed {
+            let key = &manual_keys[&m.dest];
+            if let Some(v) = by_path.remove(key) {
+                by_path.insert(key.clone(), manual::with_linked_hash(v, &m));
+            }
+            let mut done = FileReport::new(key, FileAction::Manual, Outcome::Ok);
+            done.location = Some(m.dest.clone());
+            report.file(done);
         }
+        // Leave still-missing files out of the manifest so the next run asks again
+        for m in &pending {
+            let key = &manual_keys[&m.dest];
+            by_path.remove(key);
+            new_paths.remove(key);
+        }
+        report.phase("manual", started);
     }
+    let started = Instant::now();
     // Build cached_files preserving existing order from previous manifest,
     // then append new files in index.toml order (mimics Kotlin's completion service behavior)
     let mut cached_files = serde_json::Map::new();
@@ -113,14 +392,19 @@ pub async fn run_update(opts: Options) -> Result<()> {
 
     // Then add any new files in index.toml order
     for index_entry in &index_toml.files {
-        let entry_path = index_entry.file.clone();
-        if let Some(v) = by_path.remove(&entry_path) {
-            cached_files.insert(entry_path, v);
+        let key = manifest_key(index_entry);
+        if let Some((key, v)) = by_path.remove_entry(key) {
+            cached_files.insert(key, v);
         }
     }
 
     // Cleanup unreferenced
-    remove_unreferenced(&prev, &new_paths, &opts.pack_folder);
+    for (path, location) in remove_unreferenced(&prev, &new_paths, &opts.pack_folder) {
+        let mut removed = FileReport::new(path, FileAction::Remove, Outcome::Ok);
+        removed.location = Some(location);
+        removed.reason = Some("no longer in the pack".into());
+        report.file(removed);
+    }
 
     // Write manifest
     let manifest = crate::metadata::manifest::ManifestFile {
@@ -140,10 +424,388 @@ pub async fn run_update(opts: Options) -> Result<()> {
     serde_json::to_writer(&mut f, &manifest)?;
     use std::io::Write as _;
     writeln!(&mut f)?;
+    save_pack_cache(&pack_cache);
 
+    finish_instance(&opts, &pack_toml).await?;
+    report.phase("finalize", started);
+
+    if !pending.is_empty() {
+        return Err(ManualDownloadsMissing(pending).into());
+    }
     Ok(())
 }
 
+/// Verifies the installed files against the manifest instead of updating.
+/// Nothing is downloaded or restored, so missing files fail the run.
+fn run_offline(opts: &Options, reason: &str) -> Result<()> {
+    tracing::warn!("not checking for updates: {reason}");
+    opts.report.offline(reason);
+    crate::task::verify::run_verify(&opts.pack_folder, &opts.meta_file, &opts.report)
+}
+
+/// Whether fetching the pack failed because its host is down or unreachable,
+/// rather than because it rejected the request
+fn is_unreachable(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| {
+            e.is_connect() || e.is_timeout() || e.status().is_some_and(|s| s.is_server_error())
+        })
+}
+
+/// Whether the pack is installed exactly as the last run left it, so nothing
+/// past pack.toml has to be fetched: same pack.toml and side, no optional mods
+/// picked on the command line, and every file still in place.
+fn up_to_date(opts: &Options, prev: &PreviousManifest, loaded: &LoadedPack) -> bool {
+    prev.pack_hash.as_deref() == Some(loaded.pack_hash_sha256.as_str())
+        && prev.side == Some(opts.side)
+        && matches!(opts.optional_mode, crate::cli::OptionalMode::Default)
+        && opts.option_selection.is_empty()
+        // files still waiting for a manual download are left out of the manifest
+        && loaded
+            .index
+            .files
+            .iter()
+            .all(|f| prev.cached_paths.contains(manifest_key(f)))
+        && prev
+            .locations
+            .values()
+            .all(|l| opts.pack_folder.join(l).exists())
+}
+
+fn save_pack_cache(cache: &PackCache) {
+    if let Err(e) = cache.save() {
+        tracing::warn!("failed to save pack.toml cache validators: {e:#}");
+    }
+}
+
+/// Brings the launcher instance and server loader in line with the pack
+async fn finish_instance(opts: &Options, pack_toml: &PackFileToml) -> Result<()> {
+    // Keep the launcher instance on the pack's Minecraft and loader versions
+    let multimc_folder = opts
+        .multimc_folder
+        .clone()
+        .or_else(|| opts.pack_folder.parent().map(PathBuf::from));
+    if let Some(folder) = multimc_folder
+        && crate::task::launcher::update_mmc_pack(&folder, &pack_toml.versions)?
+    {
+        tracing::warn!(
+            "Minecraft or mod loader versions changed in {}; restart the instance to apply them",
+            folder.join("mmc-pack.json").display()
+        );
+    }
+
+    if let Some(repos) = opts.loader.as_ref() {
+        if opts.side == crate::destination::side::Side::Client {
+            tracing::warn!("--install-loader only installs servers; ignoring it for --side client");
+        } else {
+            install_server_loader(
+                &pack_toml.versions,
+                &opts.pack_folder,
+                &opts.cache_dir,
+                repos,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The report entry for a processed index entry
+fn file_report(er: &EntryResult) -> FileReport {
+    let outcome = if er.manual.is_some() {
+        Outcome::Pending
+    } else {
+        Outcome::Ok
+    };
+    let mut file = FileReport::new(&er.path, er.action, outcome);
+    file.location = er
+        .value
+        .get("cachedLocation")
+        .and_then(|l| l.as_str())
+        .filter(|l| *l != er.path)
+        .map(str::to_string);
+    file.reason = er.reason.map(str::to_string);
+    file
+}
+
 fn super_hash_sha256(data: &[u8]) -> String {
     crate::sha256_hex(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::fetcher::{self, ApiRequest, ApiResponse, Fetcher};
+    use crate::request::test_server::{Response, TestServer};
+    use crate::task::test_pack::{TempDir, TestPack, mod_toml, options};
+    use futures::future::BoxFuture;
+    use reqwest::{Method, Url};
+    use std::sync::{Arc, Mutex};
+
+    /// Serves a pack, its files and the CurseForge and Modrinth APIs from memory
+    #[derive(Clone, Default)]
+    struct MemFetcher {
+        files: Arc<HashMap<String, Bytes>>,
+        calls: Arc<Mutex<Vec<ApiRequest>>>,
+    }
+
+    impl Fetcher for MemFetcher {
+        fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<Bytes>> {
+            Box::pin(async move {
+                self.files
+                    .get(url.as_str())
+                    .cloned()
+                    .with_context(|| format!("{url} not found"))
+            })
+        }
+
+        fn send<'a>(&'a self, req: &'a ApiRequest) -> BoxFuture<'a, Result<ApiResponse>> {
+            Box::pin(async move {
+                self.calls.lock().unwrap().push(req.clone());
+                let body = match (&req.method, req.url.as_str()) {
+                    (&Method::POST, "mem://curseforge/v1/mods/files") => serde_json::json!({
+                        "data": [{ "id": 100, "modId": 10, "downloadUrl": "mem://pack/jars/cf.jar" }]
+                    }),
+                    (&Method::GET, "mem://modrinth/v2/version/v1") => serde_json::json!({
+                        "files": [{
+                            "url": "mem://pack/jars/mr.jar",
+                            "filename": "mr.jar",
+                            "primary": true,
+                            "hashes": { "sha1": crate::hash_hex("sha1", b"mr")? },
+                        }]
+                    }),
+                    _ => anyhow::bail!("unexpected {} {}", req.method, req.url),
+                };
+                Ok(ApiResponse {
+                    status: reqwest::StatusCode::OK,
+                    headers: Default::default(),
+                    body: Bytes::from(body.to_string()),
+                })
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn installs_through_registered_fetcher() {
+        let pack = TestPack::new()
+            .file("config/options.txt", "fov:90\n")
+            .jar("url", "")
+            .metafile(
+                "mods/cf.pw.toml",
+                &mod_toml(
+                    "cf",
+                    "mode = \"metadata:curseforge\"",
+                    "\n[update.curseforge]\nproject-id = 10\nfile-id = 100\n",
+                ),
+            )
+            .extra("jars/cf.jar", "cf")
+            .metafile(
+                "mods/mr.pw.toml",
+                &mod_toml(
+                    "mr",
+                    "url = \"../jars/gone.jar\"",
+                    "\n[update.modrinth]\nmod-id = \"m\"\nversion = \"v1\"\n",
+                ),
+            )
+            .extra("jars/mr.jar", "mr")
+            .jar("opt", "\n[option]\noptional = true\ndefault = false\n");
+        let mem = MemFetcher {
+            files: Arc::new(
+                pack.files()
+                    .into_iter()
+                    .map(|(path, data)| (format!("mem://pack/{path}"), data))
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        fetcher::register("mem", mem.clone());
+        let dir = TempDir::new("update-mem");
+        let opts = || {
+            let mut opts = options("mem://pack/pack.toml", dir.path());
+            opts.modrinth_api_base = "mem://modrinth/v2".into();
+            opts.curseforge.base_url = "mem://curseforge".into();
+            opts
+        };
+
+        run_update(opts()).await.unwrap();
+
+        assert_eq!(dir.read("pack/config/options.txt"), "fov:90\n");
+        assert_eq!(dir.read("pack/mods/url.jar"), "url");
+        assert_eq!(dir.read("pack/mods/cf.jar"), "cf");
+        assert_eq!(dir.read("pack/mods/mr.jar"), "mr");
+        assert!(!dir.join("pack/mods/opt.jar").exists());
+
+        let manifest: serde_json::Value =
+            serde_json::from_str(&dir.read("pack/packwiz.json")).unwrap();
+        let cached = manifest["cachedFiles"].as_object().unwrap();
+        let keys: Vec<&str> = cached.keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            [
+                "config/options.txt",
+                "mods/url.pw.toml",
+                "mods/cf.pw.toml",
+                "mods/mr.pw.toml",
+                "mods/opt.pw.toml"
+            ]
+        );
+        assert_eq!(cached["mods/opt.pw.toml"]["optionValue"], false);
+
+        let calls = mem.calls.lock().unwrap().clone();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].headers["x-api-key"], "test-key");
+        let body: serde_json::Value =
+            serde_json::from_slice(calls[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "fileIds": [100] }));
+        assert_eq!(calls[1].url.as_str(), "mem://modrinth/v2/version/v1");
+
+        // Nothing changed, so the second run stops after pack.toml
+        run_update(opts()).await.unwrap();
+        assert_eq!(mem.calls.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_verifying_when_the_pack_host_is_down() {
+        let dir = TempDir::new("update-offline-fallback");
+        let pack = TestPack::new()
+            .preserved("config/keys.txt", "jump=space\n")
+            .jar("a", "")
+            .write(&dir.join("src"));
+        run_update(options(&pack, dir.path())).await.unwrap();
+        std::fs::write(dir.join("pack/config/keys.txt"), "jump=w\n").unwrap();
+
+        // nothing listens on a port that was just freed
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let down = format!("http://127.0.0.1:{port}/pack.toml");
+        let opts = options(&down, dir.path());
+        let report = opts.report.clone();
+        run_update(opts).await.unwrap();
+        let offline = report.report().offline.unwrap();
+        assert!(
+            offline.starts_with("the pack host could not be reached"),
+            "{offline}"
+        );
+        assert_eq!(dir.read("pack/config/keys.txt"), "jump=w\n");
+
+        // an incomplete instance can't be used without the pack
+        std::fs::remove_file(dir.join("pack/mods/a.jar")).unwrap();
+        let err = run_update(options(&down, dir.path())).await.unwrap_err();
+        assert!(format!("{err:#}").contains("the installed pack can't be used"));
+        assert_eq!(crate::exit_code(&err), crate::EXIT_NETWORK);
+    }
+
+    #[tokio::test]
+    async fn leaves_pending_manual_downloads_out_of_the_manifest() {
+        // CurseForge won't hand out file 200, so it has to be downloaded by hand
+        let api = TestServer::start(|req| match req.path.as_str() {
+            "/v1/mods/files" => Response::json(&serde_json::json!({ "data": [
+                { "id": 200, "modId": 20, "downloadUrl": null },
+            ]})),
+            "/v1/mods" => Response::json(&serde_json::json!({ "data": [
+                { "id": 20, "links": { "websiteUrl": "https://www.curseforge.com/minecraft/mc-mods/b" } },
+            ]})),
+            _ => Response::new(404, ""),
+        });
+        let dir = TempDir::new("update-manual");
+        let pack = TestPack::new()
+            .jar("a", "")
+            .metafile(
+                "mods/b.pw.toml",
+                &mod_toml(
+                    "b",
+                    "mode = \"metadata:curseforge\"",
+                    "\n[update.curseforge]\nproject-id = 20\nfile-id = 200\n",
+                ),
+            )
+            .write(&dir.join("src"));
+        let opts = || {
+            let mut opts = options(&pack, dir.path());
+            opts.curseforge.base_url = api.url("");
+            opts
+        };
+
+        let opts1 = opts();
+        let report = opts1.report.clone();
+        let err = run_update(opts1).await.unwrap_err();
+        assert!(err.is::<ManualDownloadsMissing>(), "{err:#}");
+        assert_eq!(crate::exit_code(&err), crate::EXIT_MANUAL_DOWNLOAD);
+        let [pending] = &report.report().manual_downloads[..] else {
+            panic!("one manual download expected")
+        };
+        assert_eq!(pending.filename, "b.jar");
+        assert_eq!(pending.dest, "mods/b.jar");
+        assert!(
+            pending
+                .url
+                .starts_with("https://www.curseforge.com/minecraft/mc-mods/b")
+        );
+        assert_eq!(dir.read("pack/mods/a.jar"), "a");
+        assert!(!dir.join("pack/mods/b.jar").exists());
+        let manifest: serde_json::Value =
+            serde_json::from_str(&dir.read("pack/packwiz.json")).unwrap();
+        let keys: Vec<&String> = manifest["cachedFiles"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        assert_eq!(keys, ["mods/a.pw.toml"]);
+
+        // the next run asks again and picks the file up from the downloads folder
+        std::fs::create_dir_all(dir.join("downloads")).unwrap();
+        std::fs::write(dir.join("downloads/b.jar"), "b").unwrap();
+        run_update(opts()).await.unwrap();
+        assert_eq!(dir.read("pack/mods/b.jar"), "b");
+        let manifest: serde_json::Value =
+            serde_json::from_str(&dir.read("pack/packwiz.json")).unwrap();
+        assert!(manifest["cachedFiles"]["mods/b.pw.toml"].is_object());
+    }
+
+    #[tokio::test]
+    async fn stored_choices_apply_until_overridden() {
+        let dir = TempDir::new("update-stored-choice");
+        let pack = |version: &str| {
+            TestPack::new()
+                .file("config/version.txt", version)
+                .jar("opt", "\n[option]\noptional = true\ndefault = true\n")
+                .write(&dir.join("src"))
+        };
+        let option_value = || {
+            let manifest: serde_json::Value =
+                serde_json::from_str(&dir.read("pack/packwiz.json")).unwrap();
+            manifest["cachedFiles"]["mods/opt.pw.toml"]["optionValue"].clone()
+        };
+        let select = |enable: &[&str], disable: &[&str]| OptionSelection {
+            enable: enable.iter().map(|p| p.to_string()).collect(),
+            disable: disable.iter().map(|p| p.to_string()).collect(),
+        };
+
+        let mut opts = options(&pack("1"), dir.path());
+        opts.option_selection = select(&[], &["opt"]);
+        run_update(opts).await.unwrap();
+        assert!(!dir.join("pack/mods/opt.jar").exists());
+        assert_eq!(option_value(), false);
+
+        // a new pack version keeps the stored choice over the pack's default
+        run_update(options(&pack("2"), dir.path())).await.unwrap();
+        assert_eq!(dir.read("pack/config/version.txt"), "2");
+        assert!(!dir.join("pack/mods/opt.jar").exists());
+        assert_eq!(option_value(), false);
+
+        // until it is changed explicitly
+        let mut opts = options(&pack("3"), dir.path());
+        opts.option_selection = select(&["opt"], &[]);
+        run_update(opts).await.unwrap();
+        assert_eq!(dir.read("pack/mods/opt.jar"), "opt");
+        assert_eq!(option_value(), true);
+
+        run_update(options(&pack("4"), dir.path())).await.unwrap();
+        assert_eq!(dir.read("pack/config/version.txt"), "4");
+        assert_eq!(dir.read("pack/mods/opt.jar"), "opt");
+        assert_eq!(option_value(), true);
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::errors::VerificationFailed;
+use crate::metadata::manifest::ManifestFile;
+use crate::task::report::{FileAction, FileReport, Outcome, Reporter};
+
+/// Checks every installed file against the hash recorded in the manifest.
+/// Files the pack marks `preserve` only have to exist. Uses only the
+/// manifest, so it works without network access.
+pub fn run_verify(pack_folder: &Path, meta_file: &str, report: &Reporter) -> Result<()> {
+    let manifest_path = pack_folder.join(meta_file);
+    let text = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("failed to read {}", manifest_path.display()))?;
+    let manifest: ManifestFile = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse {}", manifest_path.display()))?;
+
+    let mut ok = 0usize;
+    let mut problems = Vec::new();
+    for (key, entry) in &manifest.cachedFiles {
+        let Some(location) = entry.get("cachedLocation").and_then(|l| l.as_str()) else {
+            // disabled optional mods and other-side mods have nothing on disk
+            continue;
+        };
+        // metafiles record the content hash as linkedFileHash
+        let hash = entry.get("linkedFileHash").or_else(|| entry.get("hash"));
+        let (Some(fmt), Some(expected)) = (
+            hash.and_then(|h| h.get("type")).and_then(|t| t.as_str()),
+            hash.and_then(|h| h.get("value")).and_then(|v| v.as_str()),
+        ) else {
+            problems.push((key, location, format!("no hash recorded for {key}")));
+            continue;
+        };
+        let path = pack_folder.join(location);
+        if !path.exists() {
+            problems.push((key, location, "missing".to_string()));
+            continue;
+        }
+        let got = crate::hash_file_hex(fmt, &path)?;
+        let matches = got.eq_ignore_ascii_case(expected);
+        // preserved files are the user's to edit
+        let preserved = entry.get("preserve").and_then(|p| p.as_bool()) == Some(true);
+        if matches || preserved {
+            ok += 1;
+            let mut file = FileReport::new(key, FileAction::Verify, Outcome::Ok);
+            file.location = (location != key).then(|| location.to_string());
+            file.reason = (!matches).then(|| "preserved, changed locally".to_string());
+            report.file(file);
+        } else {
+            problems.push((
+                key,
+                location,
+                format!("hash mismatch (got {got}, expected {expected} ({fmt}))"),
+            ));
+        }
+    }
+
+    for (key, location, problem) in &problems {
+        let mut file = FileReport::new(*key, FileAction::Verify, Outcome::Failed);
+        file.location = (location != key).then(|| location.to_string());
+        file.error = Some(problem.clone());
+        report.file(file);
+        if report.is_text() {
+            println!("{location}: {problem}");
+        }
+    }
+    if report.is_text() {
+        println!("{} files ok, {} problems", ok, problems.len());
+    }
+    if !problems.is_empty() {
+        return Err(VerificationFailed(problems.len()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::OutputFormat;
+    use crate::task::test_pack::{TempDir, TestPack, options};
+    use crate::task::update::run_update;
+
+    #[tokio::test]
+    async fn accepts_edited_preserved_files() {
+        let dir = TempDir::new("verify-preserve");
+        let pack = TestPack::new()
+            .preserved("config/keys.txt", "jump=space\n")
+            .file("config/pack.txt", "pack\n")
+            .write(&dir.join("src"));
+        let folder = dir.join("pack");
+        run_update(options(&pack, dir.path())).await.unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_str(&dir.read("pack/packwiz.json")).unwrap();
+        assert_eq!(manifest["cachedFiles"]["config/keys.txt"]["preserve"], true);
+        assert!(manifest["cachedFiles"]["config/pack.txt"]["preserve"].is_null());
+
+        std::fs::write(folder.join("config/keys.txt"), "jump=w\n").unwrap();
+        let report = Reporter::new(OutputFormat::Text, "verify");
+        run_verify(&folder, "packwiz.json", &report).unwrap();
+        let files = report.report().files;
+        let keys = files.iter().find(|f| f.path == "config/keys.txt").unwrap();
+        assert_eq!(keys.outcome, Outcome::Ok);
+        assert_eq!(keys.reason.as_deref(), Some("preserved, changed locally"));
+
+        // an update keeps the edit, and the result still verifies
+        run_update(options(&pack, dir.path())).await.unwrap();
+        assert_eq!(dir.read("pack/config/keys.txt"), "jump=w\n");
+        run_verify(&folder, "packwiz.json", &report).unwrap();
+
+        // files that aren't preserved still have to match
+        std::fs::write(folder.join("config/pack.txt"), "edited\n").unwrap();
+        let err = run_verify(&folder, "packwiz.json", &report).unwrap_err();
+        assert!(err.is::<VerificationFailed>());
+    }
+}